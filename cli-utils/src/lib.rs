@@ -7,7 +7,7 @@ use syntect::{
     parsing::SyntaxSet,
     util::{as_24_bit_terminal_escaped, LinesWithEndings},
 };
-use xreq_lib::{KeyVal, KeyValType};
+use xreq_lib::{CassetteMode, KeyVal, KeyValType};
 
 /// Parse a single key-value pair
 /// - if key has no any prefix, it is for query
@@ -32,6 +32,29 @@ pub fn parse_key_val(s: &str) -> Result<KeyVal> {
     Ok(KeyVal::new(kv_type, key, val))
 }
 
+/// Parse a `key=value` pair for the `{{ key }}` placeholders in the config file
+pub fn parse_var(s: &str) -> Result<(String, String)> {
+    let (key, val) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("invalid variable {}, expected key=value", s))?;
+    if key.is_empty() {
+        return Err(anyhow::anyhow!("empty variable name in {}", s));
+    }
+    Ok((key.to_string(), val.to_string()))
+}
+
+/// The cassette file and mode given with the `--record` or `--replay` option.
+pub fn get_cassette(
+    record: &Option<PathBuf>,
+    replay: &Option<PathBuf>,
+) -> Option<(PathBuf, CassetteMode)> {
+    match (record, replay) {
+        (Some(file), _) => Some((file.clone(), CassetteMode::Record)),
+        (_, Some(file)) => Some((file.clone(), CassetteMode::Replay)),
+        _ => None,
+    }
+}
+
 pub fn get_config_file(s: &str) -> Result<PathBuf> {
     let path = Path::new(s);
     if path.exists() {
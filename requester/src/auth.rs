@@ -0,0 +1,319 @@
+mod oauth2;
+mod sigv4;
+
+use crate::ClientConfig;
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use http::{
+    header::{HeaderName, AUTHORIZATION},
+    HeaderValue,
+};
+use reqwest::Request;
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Sha512};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub use oauth2::{GrantType, OAuth2Auth};
+pub use sigv4::AwsSigV4Auth;
+
+/// How the request is authenticated. The credentials are applied when the request is sent, so
+/// they don't need to be pasted into the headers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Auth {
+    /// `authorization: Basic <base64 of username:password>`
+    Basic {
+        username: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        password: Option<Secret>,
+    },
+    /// `authorization: Bearer <token>`
+    Bearer { token: Secret },
+    /// an API key sent in a header or in the query string
+    ApiKey {
+        name: String,
+        value: Secret,
+        #[serde(skip_serializing_if = "is_default", default)]
+        location: ApiKeyLocation,
+    },
+    /// sign the request with a shared secret
+    Hmac(HmacAuth),
+    /// bearer token from an OAuth2 token endpoint
+    #[serde(rename = "oauth2")]
+    OAuth2(OAuth2Auth),
+    /// AWS Signature Version 4
+    #[serde(rename = "aws_sigv4")]
+    AwsSigV4(AwsSigV4Auth),
+}
+
+/// A credential, given inline (usually as a `{{ var }}` placeholder), or read from an environment
+/// variable or a file when the request is sent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Secret {
+    Value(String),
+    Env {
+        env: String,
+    },
+    /// the path is relative to the config file, surrounding whitespace of the content is trimmed
+    File {
+        file: PathBuf,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyLocation {
+    #[default]
+    Header,
+    Query,
+}
+
+/// HMAC signature of the request. The signed string is the unix timestamp, the method, the path
+/// with the query string and the body, separated by newlines:
+///
+/// ```text
+/// 1700000000\nPOST\n/orders?dry_run=true\n{"id":1}
+/// ```
+///
+/// The signature is sent in `header`, and the timestamp in `timestamp_header`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HmacAuth {
+    pub secret: Secret,
+    #[serde(default = "default_signature_header")]
+    pub header: String,
+    #[serde(default = "default_timestamp_header")]
+    pub timestamp_header: String,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub algorithm: HmacAlgorithm,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub encoding: SignatureEncoding,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
+
+fn default_signature_header() -> String {
+    "x-signature".to_string()
+}
+
+fn default_timestamp_header() -> String {
+    "x-timestamp".to_string()
+}
+
+impl Auth {
+    /// Add the credentials to the request. `client` and `user_agent` are the settings of the
+    /// request, used for the requests to get the credentials as well.
+    pub(crate) async fn apply(
+        &self,
+        req: &mut Request,
+        client: &ClientConfig,
+        user_agent: &str,
+    ) -> Result<()> {
+        match self {
+            Auth::Basic { username, password } => {
+                let password = match password {
+                    Some(password) => password.resolve().await?,
+                    None => String::new(),
+                };
+                let credentials = STANDARD.encode(format!("{}:{}", username, password));
+                set_header(req, AUTHORIZATION, &format!("Basic {}", credentials))?;
+            }
+            Auth::Bearer { token } => {
+                let token = token.resolve().await?;
+                set_header(req, AUTHORIZATION, &format!("Bearer {}", token))?;
+            }
+            Auth::ApiKey {
+                name,
+                value,
+                location,
+            } => {
+                let value = value.resolve().await?;
+                match location {
+                    ApiKeyLocation::Header => set_header(req, HeaderName::from_str(name)?, &value)?,
+                    ApiKeyLocation::Query => {
+                        req.url_mut().query_pairs_mut().append_pair(name, &value);
+                    }
+                }
+            }
+            Auth::Hmac(hmac) => {
+                let secret = hmac.secret.resolve().await?;
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                hmac.sign(req, &secret, timestamp)?;
+            }
+            Auth::OAuth2(oauth2) => {
+                // the token endpoint is reached through the same proxy and TLS settings
+                let resolve = client.resolve_overrides(&oauth2.token_url)?;
+                let token = oauth2.token(&client.client(user_agent, resolve)?).await?;
+                set_header(req, AUTHORIZATION, &format!("Bearer {}", token))?;
+            }
+            Auth::AwsSigV4(sigv4) => sigv4.apply(req).await?,
+        }
+        Ok(())
+    }
+
+    /// Make the relative file paths relative to `dir`.
+    pub(crate) fn resolve_paths(&mut self, dir: &Path) {
+        let secret = match self {
+            Auth::Basic { password, .. } => match password {
+                Some(password) => password,
+                None => return,
+            },
+            Auth::Bearer { token } => token,
+            Auth::ApiKey { value, .. } => value,
+            Auth::Hmac(hmac) => &mut hmac.secret,
+            Auth::OAuth2(oauth2) => return oauth2.resolve_paths(dir),
+            Auth::AwsSigV4(_) => return,
+        };
+        secret.resolve_path(dir);
+    }
+}
+
+impl Secret {
+    pub(crate) async fn resolve(&self) -> Result<String> {
+        match self {
+            Secret::Value(v) => Ok(v.clone()),
+            Secret::Env { env } => std::env::var(env)
+                .map_err(|_| anyhow!("environment variable {} for the credential is not set", env)),
+            Secret::File { file } => {
+                let content = tokio::fs::read_to_string(file).await.map_err(|e| {
+                    anyhow!("failed to read credential file {}: {}", file.display(), e)
+                })?;
+                Ok(content.trim().to_string())
+            }
+        }
+    }
+
+    pub(crate) fn resolve_path(&mut self, dir: &Path) {
+        if let Secret::File { file } = self {
+            if file.is_relative() {
+                *file = dir.join(&file);
+            }
+        }
+    }
+}
+
+impl HmacAuth {
+    fn sign(&self, req: &mut Request, secret: &str, timestamp: u64) -> Result<()> {
+        let body = match req.body() {
+            Some(body) => body.as_bytes().ok_or_else(|| {
+                anyhow!("hmac signing doesn't support streamed (multipart) bodies")
+            })?,
+            None => &[],
+        };
+        let url = req.url();
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let mut message = format!("{}\n{}\n{}\n", timestamp, req.method(), path).into_bytes();
+        message.extend_from_slice(body);
+
+        let signature = match self.algorithm {
+            HmacAlgorithm::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+                mac.update(&message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            HmacAlgorithm::Sha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(secret.as_bytes())?;
+                mac.update(&message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        };
+        let signature = match self.encoding {
+            SignatureEncoding::Hex => hex::encode(signature),
+            SignatureEncoding::Base64 => STANDARD.encode(signature),
+        };
+
+        set_header(
+            req,
+            HeaderName::from_str(&self.timestamp_header)?,
+            &timestamp.to_string(),
+        )?;
+        set_header(req, HeaderName::from_str(&self.header)?, &signature)
+    }
+}
+
+fn set_header(req: &mut Request, name: HeaderName, value: &str) -> Result<()> {
+    let mut value = HeaderValue::from_str(value)?;
+    value.set_sensitive(true);
+    req.headers_mut().insert(name, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: &str) -> Request {
+        reqwest::Client::new()
+            .post("http://localhost/orders?dry_run=true")
+            .body(body.to_string())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn auth_should_add_credentials() {
+        let mut req = request("");
+        let auth = Auth::Basic {
+            username: "alice".into(),
+            password: Some(Secret::Value("secret".into())),
+        };
+        auth.apply(&mut req, &ClientConfig::default(), "xreq-test")
+            .await
+            .unwrap();
+        assert_eq!(req.headers()[AUTHORIZATION], "Basic YWxpY2U6c2VjcmV0");
+
+        std::env::set_var("XREQ_TEST_BEARER_TOKEN", "abc");
+        let auth: Auth =
+            serde_yaml::from_str("type: bearer\ntoken:\n  env: XREQ_TEST_BEARER_TOKEN").unwrap();
+        auth.apply(&mut req, &ClientConfig::default(), "xreq-test")
+            .await
+            .unwrap();
+        assert_eq!(req.headers()[AUTHORIZATION], "Bearer abc");
+
+        let auth: Auth =
+            serde_yaml::from_str("type: api_key\nname: api_key\nvalue: k1\nlocation: query")
+                .unwrap();
+        auth.apply(&mut req, &ClientConfig::default(), "xreq-test")
+            .await
+            .unwrap();
+        assert_eq!(req.url().query(), Some("dry_run=true&api_key=k1"));
+    }
+
+    #[test]
+    fn hmac_should_sign_request() {
+        let mut req = request(r#"{"id":1}"#);
+        let auth: HmacAuth = serde_yaml::from_str("secret: key").unwrap();
+        auth.sign(&mut req, "key", 1700000000).unwrap();
+        assert_eq!(req.headers()["x-timestamp"], "1700000000");
+        assert_eq!(
+            req.headers()["x-signature"],
+            "380469b7ff82406bd3c97903f40d928752d94863d8de5d6d22aece7a590e7449"
+        );
+    }
+}
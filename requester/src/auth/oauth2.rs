@@ -0,0 +1,378 @@
+use super::Secret;
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+/// A cached token is refreshed this many seconds before it expires, so it doesn't expire in flight.
+const EXPIRY_MARGIN_SECS: u64 = 30;
+
+/// How long a token is cached when the token endpoint doesn't give its `expires_in`.
+const DEFAULT_TTL_SECS: u64 = 3600;
+
+/// Bearer token obtained from an OAuth2 token endpoint. The token is cached on disk until it
+/// expires, so it's shared by all the requests and runs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OAuth2Auth {
+    pub token_url: Url,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub grant_type: GrantType,
+    pub client_id: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub client_secret: Option<Secret>,
+    /// required for the `refresh_token` grant
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub refresh_token: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub scope: Option<String>,
+    /// where to cache the token, `$XDG_CACHE_HOME/xreq/tokens` (or `~/.cache/xreq/tokens`) by
+    /// default. The path is relative to the config file.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub cache_file: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantType {
+    #[default]
+    ClientCredentials,
+    RefreshToken,
+}
+
+/// Successful response of the token endpoint.
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedToken {
+    access_token: String,
+    /// unix timestamp, the token doesn't expire if it's not given (in caches written by earlier
+    /// versions)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    expires_at: Option<u64>,
+    /// the latest refresh token, since the token endpoint may rotate it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    refresh_token: Option<String>,
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
+
+fn now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+impl OAuth2Auth {
+    /// Get the access token, from the cache if it's still valid, or from the token endpoint with
+    /// the client.
+    pub(crate) async fn token(&self, client: &Client) -> Result<String> {
+        let cache_file = self.cache_file()?;
+        let cached = read_cache(&cache_file).await;
+        let now = now()?;
+        if let Some(token) = &cached {
+            if token
+                .expires_at
+                .is_none_or(|t| t > now + EXPIRY_MARGIN_SECS)
+            {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let refresh_token = match cached.and_then(|t| t.refresh_token) {
+            Some(token) if self.grant_type == GrantType::RefreshToken => Some(token),
+            _ => match &self.refresh_token {
+                Some(token) => Some(token.resolve().await?),
+                None => None,
+            },
+        };
+        let res = self.fetch(client, refresh_token).await?;
+
+        let token = CachedToken {
+            access_token: res.access_token,
+            expires_at: Some(now + res.expires_in.unwrap_or(DEFAULT_TTL_SECS)),
+            refresh_token: res.refresh_token,
+        };
+        write_cache(&cache_file, &token).await?;
+        Ok(token.access_token)
+    }
+
+    /// Expire the cached token, e.g. after the server rejected it, so the next request gets a new
+    /// one. The refresh token is kept, since the token endpoint may have rotated it.
+    pub(crate) async fn invalidate(&self) -> Result<()> {
+        let cache_file = self.cache_file()?;
+        match read_cache(&cache_file).await {
+            Some(token) => {
+                let token = CachedToken {
+                    expires_at: Some(0),
+                    ..token
+                };
+                write_cache(&cache_file, &token).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn resolve_paths(&mut self, dir: &Path) {
+        if let Some(file) = self.cache_file.as_mut() {
+            if file.is_relative() {
+                *file = dir.join(&file);
+            }
+        }
+        for secret in [self.client_secret.as_mut(), self.refresh_token.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            secret.resolve_path(dir);
+        }
+    }
+
+    async fn fetch(&self, client: &Client, refresh_token: Option<String>) -> Result<TokenResponse> {
+        let mut form = vec![];
+        match self.grant_type {
+            GrantType::ClientCredentials => form.push(("grant_type", "client_credentials".into())),
+            GrantType::RefreshToken => {
+                let token = refresh_token.ok_or_else(|| {
+                    anyhow!("refresh_token is required for the refresh_token grant")
+                })?;
+                form.push(("grant_type", "refresh_token".into()));
+                form.push(("refresh_token", token));
+            }
+        }
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope.clone()));
+        }
+        let client_secret = match &self.client_secret {
+            Some(secret) => Some(secret.resolve().await?),
+            None => None,
+        };
+
+        let res = client
+            .post(self.token_url.clone())
+            .basic_auth(&self.client_id, client_secret)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| anyhow!("failed to get a token from {}: {}", self.token_url, e))?;
+        let status = res.status();
+        let body = res.text().await?;
+        if !status.is_success() {
+            return Err(anyhow!(
+                "token endpoint {} returned {}: {}",
+                self.token_url,
+                status,
+                body
+            ));
+        }
+        serde_json::from_str(&body).map_err(|e| {
+            anyhow!(
+                "invalid response from token endpoint {}: {}",
+                self.token_url,
+                e
+            )
+        })
+    }
+
+    /// The given cache file, or one per token endpoint, client and scope in the cache dir.
+    fn cache_file(&self) -> Result<PathBuf> {
+        if let Some(file) = &self.cache_file {
+            return Ok(file.clone());
+        }
+        let dir = match std::env::var("XDG_CACHE_HOME") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(
+                std::env::var("HOME").map_err(|_| anyhow!("can't find the cache dir"))?,
+            )
+            .join(".cache"),
+        };
+        let key = format!(
+            "{}\n{:?}\n{}\n{}",
+            self.token_url,
+            self.grant_type,
+            self.client_id,
+            self.scope.as_deref().unwrap_or_default()
+        );
+        let name = hex::encode(Sha256::digest(key.as_bytes()));
+        Ok(dir.join("xreq/tokens").join(format!("{}.json", name)))
+    }
+}
+
+/// A missing or unreadable cache is the same as no cached token.
+async fn read_cache(path: &Path) -> Option<CachedToken> {
+    let content = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+/// Write the cache to a temporary file renamed into place, so that concurrent runs never read a
+/// partly written cache.
+async fn write_cache(path: &Path, token: &CachedToken) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let write = async {
+        let mut file = options.open(&tmp).await?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, &serde_json::to_vec(token)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    if let Err(e) = write.await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(anyhow!(
+            "failed to write token cache {}: {}",
+            path.display(),
+            e
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_server::{serve, Reply},
+        Auth, ClientConfig,
+    };
+    use http::{header::AUTHORIZATION, Method};
+    use reqwest::Request;
+    use std::sync::{Arc, Mutex};
+
+    /// A token endpoint which records the request bodies, and issues tokens valid for
+    /// `expires_in` seconds.
+    async fn token_server(expires_in: u64) -> (Url, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let addr = serve(move |req| {
+            recorded.lock().unwrap().push(req.body);
+            Reply::json(format!(
+                r#"{{"access_token":"token-{}","token_type":"Bearer","expires_in":{},"refresh_token":"refresh-{}"}}"#,
+                req.n, expires_in, req.n
+            ))
+        })
+        .await;
+        let url = format!("http://{}/token", addr);
+        (url.parse().unwrap(), requests)
+    }
+
+    fn cache_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("xreq-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn client_credentials_token_should_be_cached() {
+        let (token_url, requests) = token_server(3600).await;
+        let client = Client::new();
+        let auth = OAuth2Auth {
+            token_url,
+            grant_type: GrantType::ClientCredentials,
+            client_id: "client".into(),
+            client_secret: Some(Secret::Value("secret".into())),
+            refresh_token: None,
+            scope: Some("read".into()),
+            cache_file: Some(cache_file("client-credentials")),
+        };
+        assert_eq!(auth.token(&client).await.unwrap(), "token-1");
+        assert_eq!(auth.token(&client).await.unwrap(), "token-1");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["grant_type=client_credentials&scope=read"]
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_token_should_be_refreshed() {
+        let (token_url, requests) = token_server(0).await;
+        let client = Client::new();
+        let auth = OAuth2Auth {
+            token_url,
+            grant_type: GrantType::RefreshToken,
+            client_id: "client".into(),
+            client_secret: None,
+            refresh_token: Some(Secret::Value("initial".into())),
+            scope: None,
+            cache_file: Some(cache_file("refresh-token")),
+        };
+        assert_eq!(auth.token(&client).await.unwrap(), "token-1");
+        assert_eq!(auth.token(&client).await.unwrap(), "token-2");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "grant_type=refresh_token&refresh_token=initial",
+                "grant_type=refresh_token&refresh_token=refresh-1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_token_should_be_fetched_again() {
+        // issues tokens without expires_in, and only accepts the second one
+        let addr = serve(|req| match req.lines(&["POST /token"]).is_empty() {
+            false => Reply::json(format!(r#"{{"access_token":"token-{}"}}"#, req.n)),
+            true if req.lines(&["authorization: Bearer token-3"]).is_empty() => Reply::status(401),
+            true => Reply::ok("ok"),
+        })
+        .await;
+        let cache_file = cache_file("rejected");
+        let mut ctx: crate::RequestContext = format!("http://{}/", addr).parse().unwrap();
+        ctx.auth = Some(Auth::OAuth2(OAuth2Auth {
+            token_url: format!("http://{}/token", addr).parse().unwrap(),
+            grant_type: GrantType::ClientCredentials,
+            client_id: "client".into(),
+            client_secret: None,
+            refresh_token: None,
+            scope: None,
+            cache_file: Some(cache_file.clone()),
+        }));
+
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.status(), 200);
+        let cached = read_cache(&cache_file).await.unwrap();
+        assert_eq!(cached.access_token, "token-3");
+        assert!(cached.expires_at.unwrap() >= now().unwrap() + DEFAULT_TTL_SECS - 5);
+    }
+
+    #[tokio::test]
+    async fn token_should_be_fetched_with_the_client_config() {
+        let (mut token_url, _) = token_server(3600).await;
+        let port = token_url.port().unwrap();
+        // the token endpoint is only reachable with the resolve override of the request
+        token_url.set_host(Some("auth.example.test")).unwrap();
+        let auth = Auth::OAuth2(OAuth2Auth {
+            token_url,
+            grant_type: GrantType::ClientCredentials,
+            client_id: "client".into(),
+            client_secret: None,
+            refresh_token: None,
+            scope: None,
+            cache_file: Some(cache_file("client-config")),
+        });
+        let config = ClientConfig {
+            resolve: [(format!("auth.example.test:{}", port), "127.0.0.1".into())].into(),
+            ..Default::default()
+        };
+        let mut req = Request::new(Method::GET, "http://api.example.test/".parse().unwrap());
+        auth.apply(&mut req, &config, "xreq-test").await.unwrap();
+        assert_eq!(req.headers()[AUTHORIZATION], "Bearer token-1");
+    }
+}
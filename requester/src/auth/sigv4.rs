@@ -0,0 +1,378 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use http::{header::AUTHORIZATION, HeaderValue};
+use reqwest::Request;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+/// Headers which are changed on the way by the client or proxies, so they aren't signed.
+const UNSIGNED_HEADERS: [&str; 4] = ["authorization", "user-agent", "expect", "x-amzn-trace-id"];
+
+/// AWS Signature Version 4, e.g. for API Gateway with IAM auth. The credentials come from the
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` environment variables, or
+/// from the shared credentials file (`~/.aws/credentials`, or `AWS_SHARED_CREDENTIALS_FILE`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AwsSigV4Auth {
+    pub region: String,
+    /// e.g. `execute-api` for API Gateway
+    pub service: String,
+    /// profile in the shared credentials file. If it's not given, the environment variables are
+    /// used if they're set, otherwise the `AWS_PROFILE` (or `default`) profile.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl AwsSigV4Auth {
+    /// Sign the request. The query string is canonicalized (and sent that way), so the server
+    /// computes the same signature.
+    pub(crate) async fn apply(&self, req: &mut Request) -> Result<()> {
+        let credentials = self.credentials().await?;
+        self.sign(req, &credentials, SystemTime::now())
+    }
+
+    fn sign(&self, req: &mut Request, credentials: &Credentials, time: SystemTime) -> Result<()> {
+        let (date, amz_date) = format_time(time)?;
+        let payload_hash = {
+            let body = match req.body() {
+                Some(body) => body.as_bytes().ok_or_else(|| {
+                    anyhow!("aws_sigv4 signing doesn't support streamed (multipart) bodies")
+                })?,
+                None => &[],
+            };
+            hex::encode(Sha256::digest(body))
+        };
+
+        let query = canonical_query(req.url().query());
+        req.url_mut()
+            .set_query((!query.is_empty()).then_some(query.as_str()));
+
+        let headers = req.headers_mut();
+        headers.insert("x-amz-date", HeaderValue::from_str(&amz_date)?);
+        if let Some(token) = &credentials.session_token {
+            headers.insert("x-amz-security-token", HeaderValue::from_str(token)?);
+        }
+        if self.service == "s3" {
+            headers.insert(
+                "x-amz-content-sha256",
+                HeaderValue::from_str(&payload_hash)?,
+            );
+        }
+
+        let url = req.url();
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(anyhow!("aws_sigv4 signing requires a host in the url")),
+        };
+        let mut signed: Vec<(String, String)> = vec![("host".into(), host)];
+        for name in req.headers().keys() {
+            if name == "host" || UNSIGNED_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            let values = req
+                .headers()
+                .get_all(name)
+                .iter()
+                .map(|v| Ok(normalize_space(v.to_str()?)))
+                .collect::<Result<Vec<_>>>()?;
+            signed.push((name.to_string(), values.join(",")));
+        }
+        signed.sort();
+        let signed_headers = signed
+            .iter()
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = signed
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v))
+            .collect();
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            req.method(),
+            self.canonical_path(url.path()),
+            query,
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            ALGORITHM,
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = [self.region.as_str(), self.service.as_str(), "aws4_request"]
+            .iter()
+            .try_fold(
+                hmac_sha256(
+                    format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+                    date.as_bytes(),
+                )?,
+                |key, data| hmac_sha256(&key, data.as_bytes()),
+            )?;
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes())?);
+
+        let mut value = HeaderValue::from_str(&format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            ALGORITHM, credentials.access_key_id, scope, signed_headers, signature
+        ))?;
+        value.set_sensitive(true);
+        req.headers_mut().insert(AUTHORIZATION, value);
+        Ok(())
+    }
+
+    /// The path is encoded once more, except for S3 whose canonical path is the path as sent.
+    fn canonical_path(&self, path: &str) -> String {
+        let path = if path.is_empty() { "/" } else { path };
+        if self.service == "s3" {
+            return path.to_string();
+        }
+        path.split('/')
+            .map(|segment| uri_encode(segment.as_bytes()))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    async fn credentials(&self) -> Result<Credentials> {
+        if self.profile.is_none() {
+            if let (Ok(access_key_id), Ok(secret_access_key)) = (
+                std::env::var("AWS_ACCESS_KEY_ID"),
+                std::env::var("AWS_SECRET_ACCESS_KEY"),
+            ) {
+                return Ok(Credentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+                });
+            }
+        }
+
+        let profile = match &self.profile {
+            Some(profile) => profile.clone(),
+            None => std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string()),
+        };
+        let path = match std::env::var("AWS_SHARED_CREDENTIALS_FILE") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => PathBuf::from(
+                std::env::var("HOME")
+                    .map_err(|_| anyhow!("can't find the AWS shared credentials file"))?,
+            )
+            .join(".aws/credentials"),
+        };
+        let content = tokio::fs::read_to_string(&path).await.map_err(|e| {
+            anyhow!(
+                "no AWS credentials in the environment, and failed to read {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        parse_credentials(&content, &profile).ok_or_else(|| {
+            anyhow!(
+                "AWS profile {} not found or incomplete in {}",
+                profile,
+                path.display()
+            )
+        })
+    }
+}
+
+/// Find the profile in the INI-like shared credentials file.
+fn parse_credentials(content: &str, profile: &str) -> Option<Credentials> {
+    let mut section = None;
+    let (mut access_key_id, mut secret_access_key, mut session_token) = (None, None, None);
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = Some(name.trim().to_string());
+            continue;
+        }
+        if section.as_deref() != Some(profile) {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let value = Some(value.trim().to_string());
+            match key.trim() {
+                "aws_access_key_id" => access_key_id = value,
+                "aws_secret_access_key" => secret_access_key = value,
+                "aws_session_token" => session_token = value,
+                _ => {}
+            }
+        }
+    }
+    Some(Credentials {
+        access_key_id: access_key_id?,
+        secret_access_key: secret_access_key?,
+        session_token,
+    })
+}
+
+/// Decode the query string (as serialized by `serde_qs`) and encode it again the way SigV4
+/// expects: percent encoded except for unreserved characters, sorted by name then value.
+fn canonical_query(query: Option<&str>) -> String {
+    let mut pairs: Vec<(String, String)> =
+        url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .map(|(k, v)| (uri_encode(k.as_bytes()), uri_encode(v.as_bytes())))
+            .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn uri_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn normalize_space(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Format the time as the SigV4 date (`20150830`) and timestamp (`20150830T123600Z`), in UTC.
+fn format_time(time: SystemTime) -> Result<(String, String)> {
+    let secs = time.duration_since(UNIX_EPOCH)?.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let amz_date = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    );
+    Ok((date, amz_date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        }
+    }
+
+    /// 2015-08-30T12:36:00Z, the time used in the AWS examples
+    fn time() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1440938160)
+    }
+
+    #[test]
+    fn sigv4_should_match_aws_examples() {
+        let auth = AwsSigV4Auth {
+            region: "us-east-1".into(),
+            service: "iam".into(),
+            profile: None,
+        };
+        let mut req = reqwest::Client::new()
+            .get("https://iam.amazonaws.com/?Version=2010-05-08&Action=ListUsers")
+            .header(
+                "content-type",
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )
+            .build()
+            .unwrap();
+        auth.sign(&mut req, &credentials(), time()).unwrap();
+        assert_eq!(
+            req.url().query(),
+            Some("Action=ListUsers&Version=2010-05-08")
+        );
+        assert_eq!(
+            req.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+
+        let auth = AwsSigV4Auth {
+            region: "us-east-1".into(),
+            service: "service".into(),
+            profile: None,
+        };
+        let mut req = reqwest::Client::new()
+            .get("https://example.amazonaws.com/")
+            .build()
+            .unwrap();
+        auth.sign(&mut req, &credentials(), time()).unwrap();
+        assert_eq!(
+            req.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn canonical_query_should_reencode_serde_qs_output() {
+        let params = serde_json::json!({ "q": "a b*", "filter": { "tag": "x" } });
+        let qs = serde_qs::to_string(&params).unwrap();
+        assert_eq!(canonical_query(Some(&qs)), "filter%5Btag%5D=x&q=a%20b%2A");
+    }
+
+    #[test]
+    fn parse_credentials_should_find_profile() {
+        let content = "[default]\naws_access_key_id = A\naws_secret_access_key = B\n\n\
+                       [dev]\naws_access_key_id=C\naws_secret_access_key=D\naws_session_token=E\n";
+        assert_eq!(
+            parse_credentials(content, "dev"),
+            Some(Credentials {
+                access_key_id: "C".into(),
+                secret_access_key: "D".into(),
+                session_token: Some("E".into()),
+            })
+        );
+        assert_eq!(parse_credentials(content, "prod"), None);
+    }
+}
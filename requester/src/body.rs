@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue};
+use reqwest::{
+    multipart::{Form, Part},
+    RequestBuilder,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+/// How the request body is encoded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyType {
+    /// the body is serialized as JSON
+    Json,
+    /// `application/x-www-form-urlencoded`, the body is an object (or an already encoded string)
+    Form,
+    /// `multipart/form-data`, the body is an object. A value like `{ file: <path> }` (with optional
+    /// `filename` and `content_type`) is a file part read from disk, others are text parts.
+    Multipart,
+    /// the body is a string sent as is, `text/plain` by default
+    Text,
+    /// the body is a string sent as is, `application/octet-stream` by default
+    Raw,
+    /// the body is a base64 encoded string which is decoded before sending
+    Base64,
+}
+
+impl BodyType {
+    /// Infer the body type from the content type, JSON if there's no content type.
+    pub fn from_content_type(content_type: Option<&HeaderValue>) -> Self {
+        let content_type = match content_type.and_then(|v| v.to_str().ok()) {
+            Some(v) => v.to_ascii_lowercase(),
+            None => return BodyType::Json,
+        };
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match essence {
+            "application/json" => BodyType::Json,
+            v if v.ends_with("+json") => BodyType::Json,
+            "application/x-www-form-urlencoded" => BodyType::Form,
+            "multipart/form-data" => BodyType::Multipart,
+            "application/xml" => BodyType::Text,
+            v if v.starts_with("text/") || v.ends_with("+xml") => BodyType::Text,
+            _ => BodyType::Raw,
+        }
+    }
+
+    /// The content type sent when the request doesn't set one. Multipart has none since the
+    /// boundary is generated when the body is encoded.
+    pub(crate) fn content_type(self) -> Option<&'static str> {
+        match self {
+            BodyType::Json => Some("application/json"),
+            BodyType::Form => Some("application/x-www-form-urlencoded"),
+            BodyType::Multipart => None,
+            BodyType::Text => Some("text/plain; charset=utf-8"),
+            BodyType::Raw | BodyType::Base64 => Some("application/octet-stream"),
+        }
+    }
+}
+
+/// Set the body on the request builder. `headers` are the headers the builder already has.
+pub(crate) async fn apply_body(
+    builder: RequestBuilder,
+    headers: &HeaderMap,
+    body_type: BodyType,
+    body: &Value,
+) -> Result<RequestBuilder> {
+    let builder = match (headers.get(CONTENT_TYPE), body_type.content_type()) {
+        (None, Some(default)) if body_type != BodyType::Json => {
+            builder.header(CONTENT_TYPE, default)
+        }
+        _ => builder,
+    };
+
+    let builder = match body_type {
+        BodyType::Json => builder.json(body),
+        BodyType::Form => builder.body(to_form(body)?),
+        BodyType::Multipart => builder.multipart(to_multipart(body).await?),
+        BodyType::Text | BodyType::Raw => builder.body(to_text(body)?),
+        BodyType::Base64 => {
+            let bytes = STANDARD
+                .decode(to_text(body)?.trim())
+                .map_err(|e| anyhow!("invalid base64 body: {}", e))?;
+            builder.body(bytes)
+        }
+    };
+    Ok(builder)
+}
+
+/// The body as it's sent, for all the body types but multipart. A base64 body is kept encoded.
+pub(crate) fn encode_text(body_type: BodyType, body: &Value) -> Result<String> {
+    match body_type {
+        BodyType::Json => Ok(serde_json::to_string(body)?),
+        BodyType::Form => to_form(body),
+        BodyType::Multipart => Err(anyhow!("multipart body can't be encoded as text")),
+        BodyType::Text | BodyType::Raw | BodyType::Base64 => to_text(body),
+    }
+}
+
+fn to_form(body: &Value) -> Result<String> {
+    match body {
+        Value::String(s) => Ok(s.clone()),
+        Value::Object(_) => Ok(serde_qs::to_string(body)?),
+        _ => Err(anyhow!("form body must be an object or a string")),
+    }
+}
+
+fn to_text(body: &Value) -> Result<String> {
+    match body {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(_) | Value::Bool(_) => Ok(body.to_string()),
+        _ => Err(anyhow!(
+            "body must be a string for text, raw or base64 body type"
+        )),
+    }
+}
+
+/// Make the relative paths of the file parts of a multipart body relative to `dir`.
+pub(crate) fn resolve_paths(body: &mut Value, dir: &Path) {
+    let Some(fields) = body.as_object_mut() else {
+        return;
+    };
+    for value in fields.values_mut() {
+        let values = match value {
+            Value::Array(values) => values.iter_mut().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            if let Some(Value::String(path)) = value.get_mut("file") {
+                if Path::new(path.as_str()).is_relative() {
+                    *path = dir.join(path.as_str()).to_string_lossy().into_owned();
+                }
+            }
+        }
+    }
+}
+
+async fn to_multipart(body: &Value) -> Result<Form> {
+    let fields = body
+        .as_object()
+        .ok_or_else(|| anyhow!("multipart body must be an object"))?;
+    let mut form = Form::new();
+    for (name, value) in fields {
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            form = form.part(name.clone(), to_part(name, value).await?);
+        }
+    }
+    Ok(form)
+}
+
+async fn to_part(name: &str, value: &Value) -> Result<Part> {
+    match value {
+        Value::String(s) => Ok(Part::text(s.clone())),
+        Value::Number(_) | Value::Bool(_) => Ok(Part::text(value.to_string())),
+        Value::Object(obj) => {
+            let path = obj
+                .get("file")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("multipart field {} must have a file path", name))?;
+            let data = tokio::fs::read(path)
+                .await
+                .map_err(|e| anyhow!("failed to read {} for field {}: {}", path, name, e))?;
+            let filename = obj
+                .get("filename")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+                .or_else(|| {
+                    Path::new(path)
+                        .file_name()
+                        .map(|s| s.to_string_lossy().into_owned())
+                })
+                .unwrap_or_default();
+            let part = Part::bytes(data).file_name(filename);
+            match obj.get("content_type").and_then(|v| v.as_str()) {
+                Some(content_type) => Ok(part.mime_str(content_type)?),
+                None => Ok(part),
+            }
+        }
+        _ => Err(anyhow!("unsupported value for multipart field {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn body_type_should_be_inferred_from_content_type() {
+        let infer = |v: &str| BodyType::from_content_type(Some(&HeaderValue::from_str(v).unwrap()));
+        assert_eq!(BodyType::from_content_type(None), BodyType::Json);
+        assert_eq!(infer("application/json; charset=utf-8"), BodyType::Json);
+        assert_eq!(infer("application/vnd.api+json"), BodyType::Json);
+        assert_eq!(infer("application/x-www-form-urlencoded"), BodyType::Form);
+        assert_eq!(infer("multipart/form-data"), BodyType::Multipart);
+        assert_eq!(infer("text/plain"), BodyType::Text);
+        assert_eq!(infer("application/xml"), BodyType::Text);
+        assert_eq!(infer("image/png"), BodyType::Raw);
+    }
+
+    async fn build(
+        body_type: BodyType,
+        body: Value,
+        content_type: Option<&str>,
+    ) -> (String, String) {
+        let client = reqwest::Client::new();
+        let mut headers = HeaderMap::new();
+        if let Some(v) = content_type {
+            headers.insert(CONTENT_TYPE, v.parse().unwrap());
+        }
+        let builder = client.post("http://localhost/").headers(headers.clone());
+        let req = apply_body(builder, &headers, body_type, &body)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        let content_type = req.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+        let body = req.body().unwrap().as_bytes().unwrap().to_vec();
+        (content_type, String::from_utf8(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn apply_body_should_encode_body() {
+        assert_eq!(
+            build(BodyType::Json, json!({"a": 1}), None).await,
+            ("application/json".into(), r#"{"a":1}"#.into())
+        );
+        assert_eq!(
+            build(BodyType::Form, json!({"a": 1, "b": "x y"}), None).await,
+            (
+                "application/x-www-form-urlencoded".into(),
+                "a=1&b=x+y".into()
+            )
+        );
+        assert_eq!(
+            build(BodyType::Text, json!("<a/>"), Some("application/xml")).await,
+            ("application/xml".into(), "<a/>".into())
+        );
+        assert_eq!(
+            build(BodyType::Base64, json!("aGVsbG8="), None).await,
+            ("application/octet-stream".into(), "hello".into())
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    body::{self, BodyType},
+    req::AttemptStart,
+    RequestContext,
+};
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http::{
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING},
+    HeaderMap, Method,
+};
+use reqwest::{Response, ResponseBuilderExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use tokio::sync::Mutex;
+use url::Url;
+
+/// Interactions of the cassette files used by the process, loaded on first use. The lock is held
+/// while a cassette file is written, so concurrent requests don't lose each other's recordings.
+static CASSETTES: LazyLock<Mutex<HashMap<PathBuf, Vec<Interaction>>>> =
+    LazyLock::new(Default::default);
+
+/// Record the responses to a file, or serve them back from it without network, e.g. to run the
+/// diffs in CI without access to the servers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cassette {
+    /// YAML file of the recorded requests and responses, relative to the config file
+    pub file: PathBuf,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub mode: CassetteMode,
+    /// request headers which must match as well, besides the method, url and params
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub match_headers: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    /// serve the recorded responses, a request without a recording is an error
+    #[default]
+    Replay,
+    /// send the requests and record the responses, replacing the earlier recordings of the same
+    /// requests
+    Record,
+}
+
+/// A recorded request and its response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+/// What a request is matched on. Only the headers in `match_headers` are recorded, so that
+/// credentials don't end up in the cassette, and only a hash of the body.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RecordedRequest {
+    #[serde(with = "http_serde::method")]
+    method: Method,
+    url: Url,
+    #[serde(skip_serializing_if = "is_empty_value", default = "default_params")]
+    params: Value,
+    #[serde(skip_serializing_if = "HeaderMap::is_empty", default)]
+    #[serde(with = "http_serde::header_map")]
+    headers: HeaderMap,
+    /// SHA-256 of the body as it's sent, so that e.g. GraphQL queries to the same url have their
+    /// own recordings
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    body_sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RecordedResponse {
+    status: u16,
+    #[serde(skip_serializing_if = "HeaderMap::is_empty", default)]
+    #[serde(with = "http_serde::header_map")]
+    headers: HeaderMap,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    body: String,
+    /// the body is base64 encoded, since it isn't valid UTF-8
+    #[serde(skip_serializing_if = "is_default", default)]
+    base64: bool,
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
+
+fn is_empty_value(v: &Value) -> bool {
+    v.is_null() || v.as_object().is_some_and(|o| o.is_empty())
+}
+
+fn default_params() -> Value {
+    serde_json::json!({})
+}
+
+impl Cassette {
+    pub fn new(file: impl Into<PathBuf>, mode: CassetteMode) -> Self {
+        Self {
+            file: file.into(),
+            mode,
+            match_headers: Vec::new(),
+        }
+    }
+
+    pub(crate) fn resolve_path(&mut self, dir: &Path) {
+        if self.file.is_relative() {
+            self.file = dir.join(&self.file);
+        }
+    }
+
+    /// The recorded response of the request.
+    pub(crate) async fn replay(&self, ctx: &RequestContext) -> Result<Response> {
+        let request = self.to_recorded(ctx).await?;
+        let mut cassettes = CASSETTES.lock().await;
+        let interactions = self.interactions(&mut cassettes).await?;
+        let interaction = interactions
+            .iter()
+            .find(|i| i.request == request)
+            .ok_or_else(|| {
+                anyhow!(
+                    "no recorded response for {} {} in cassette {}",
+                    ctx.method,
+                    ctx.url,
+                    self.file.display()
+                )
+            })?;
+        interaction.response.to_response(ctx.url_with_params()?)
+    }
+
+    /// Record the response of the request, and return an equivalent response since the body is
+    /// read.
+    pub(crate) async fn record(&self, ctx: &RequestContext, res: Response) -> Result<Response> {
+        let url = res.url().clone();
+        let start = res.extensions().get::<AttemptStart>().copied();
+        let response = RecordedResponse::read(res).await?;
+        let request = self.to_recorded(ctx).await?;
+
+        let mut cassettes = CASSETTES.lock().await;
+        let interactions = self.interactions(&mut cassettes).await?;
+        interactions.retain(|i| i.request != request);
+        interactions.push(Interaction {
+            request,
+            response: response.clone(),
+        });
+        if let Some(dir) = self.file.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&self.file, serde_yaml::to_string(interactions)?)
+            .await
+            .map_err(|e| anyhow!("failed to write cassette {}: {}", self.file.display(), e))?;
+
+        let mut res = response.to_response(url)?;
+        if let Some(start) = start {
+            res.extensions_mut().insert(start);
+        }
+        Ok(res)
+    }
+
+    /// The interactions of the cassette file, read on first use. A missing file has none.
+    async fn interactions<'a>(
+        &self,
+        cassettes: &'a mut HashMap<PathBuf, Vec<Interaction>>,
+    ) -> Result<&'a mut Vec<Interaction>> {
+        if !cassettes.contains_key(&self.file) {
+            let interactions = match tokio::fs::read_to_string(&self.file).await {
+                Ok(content) => serde_yaml::from_str(&content)
+                    .map_err(|e| anyhow!("invalid cassette {}: {}", self.file.display(), e))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => {
+                    return Err(anyhow!(
+                        "failed to read cassette {}: {}",
+                        self.file.display(),
+                        e
+                    ))
+                }
+            };
+            cassettes.insert(self.file.clone(), interactions);
+        }
+        Ok(cassettes.get_mut(&self.file).unwrap())
+    }
+
+    async fn to_recorded(&self, ctx: &RequestContext) -> Result<RecordedRequest> {
+        let mut headers = HeaderMap::new();
+        for name in &self.match_headers {
+            for (k, v) in ctx
+                .headers
+                .iter()
+                .filter(|(k, _)| k.as_str().eq_ignore_ascii_case(name))
+            {
+                headers.append(k.clone(), v.clone());
+            }
+        }
+        let body = match (&ctx.body, &ctx.body_file) {
+            // the multipart encoding has a random boundary, the fields are hashed instead
+            (Some(body), _) if ctx.body_type() == BodyType::Multipart => serde_json::to_vec(body)?,
+            (Some(body), _) => body::encode_text(ctx.body_type(), body)?.into_bytes(),
+            (None, Some(file)) => tokio::fs::read(file)
+                .await
+                .map_err(|e| anyhow!("failed to read body file {}: {}", file.display(), e))?,
+            (None, None) => vec![],
+        };
+        Ok(RecordedRequest {
+            method: ctx.method.clone(),
+            url: ctx.url.clone(),
+            params: ctx.params.clone(),
+            headers,
+            body_sha256: (!body.is_empty()).then(|| hex::encode(Sha256::digest(&body))),
+        })
+    }
+}
+
+impl RecordedResponse {
+    async fn read(res: Response) -> Result<Self> {
+        let status = res.status().as_u16();
+        let mut headers = res.headers().clone();
+        strip_framing(&mut headers);
+        let body = res.bytes().await?;
+        let (body, base64) = match String::from_utf8(body.to_vec()) {
+            Ok(body) => (body, false),
+            Err(_) => (STANDARD.encode(&body), true),
+        };
+        Ok(Self {
+            status,
+            headers,
+            body,
+            base64,
+        })
+    }
+
+    fn to_response(&self, url: Url) -> Result<Response> {
+        let body = match self.base64 {
+            true => STANDARD.decode(&self.body)?,
+            false => self.body.clone().into_bytes(),
+        };
+        let mut builder = http::Response::builder().status(self.status).url(url);
+        if let Some(headers) = builder.headers_mut() {
+            *headers = self.headers.clone();
+            strip_framing(headers);
+        }
+        Ok(Response::from(builder.body(body)?))
+    }
+}
+
+/// Remove the headers which describe the body as it was on the wire, since the recorded body is
+/// already decoded. Hand-written cassettes may have them too, so they're removed on replay as well.
+fn strip_framing(headers: &mut HeaderMap) {
+    for name in [CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING] {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{serve, Reply};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[tokio::test]
+    async fn cassette_should_record_and_replay() {
+        // responds with the number of requests it has served
+        let count = Arc::new(AtomicUsize::new(0));
+        let served = count.clone();
+        let addr = serve(move |req| {
+            served.store(req.n, Ordering::SeqCst);
+            Reply::json(format!(r#"{{"n":{}}}"#, req.n))
+        })
+        .await;
+
+        let file = std::env::temp_dir().join(format!("xreq-cassette-{}.yml", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let mut ctx: RequestContext = format!("http://{}/todos?id=1", addr).parse().unwrap();
+        ctx.headers.insert("x-tenant", "a".parse().unwrap());
+        let mut cassette = Cassette::new(&file, CassetteMode::Record);
+        cassette.match_headers = vec!["x-tenant".into()];
+
+        ctx.client.cassette = Some(cassette.clone());
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), r#"{"n":1}"#);
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), r#"{"n":2}"#);
+
+        cassette.mode = CassetteMode::Replay;
+        ctx.client.cassette = Some(cassette);
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.url().as_str(), format!("http://{}/todos?id=1", addr));
+        assert_eq!(res.headers()["content-type"], "application/json");
+        assert_eq!(res.text().await.unwrap(), r#"{"n":2}"#);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        // the recording is in the file, not just in memory
+        let content = std::fs::read_to_string(&file).unwrap();
+        let interactions: Vec<Interaction> = serde_yaml::from_str(&content).unwrap();
+        assert_eq!(interactions.len(), 1);
+        assert_eq!(interactions[0].request.headers["x-tenant"], "a");
+        assert!(!interactions[0]
+            .response
+            .headers
+            .contains_key("content-length"));
+
+        ctx.headers.insert("x-tenant", "b".parse().unwrap());
+        let err = ctx.send().await.unwrap_err();
+        assert!(err.to_string().starts_with("no recorded response for GET"));
+
+        // requests which differ only by their body have their own recordings
+        ctx.headers.insert("x-tenant", "a".parse().unwrap());
+        ctx.body = Some(serde_json::json!({"query": "a"}));
+        let err = ctx.send().await.unwrap_err();
+        assert!(err.to_string().starts_with("no recorded response for GET"));
+        let set_mode = |ctx: &mut RequestContext, mode| {
+            ctx.client.cassette.as_mut().unwrap().mode = mode;
+        };
+        set_mode(&mut ctx, CassetteMode::Record);
+        ctx.send().await.unwrap();
+        set_mode(&mut ctx, CassetteMode::Replay);
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), r#"{"n":3}"#);
+        ctx.body = None;
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), r#"{"n":2}"#);
+        let _ = std::fs::remove_file(&file);
+    }
+}
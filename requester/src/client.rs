@@ -0,0 +1,622 @@
+use crate::cassette::Cassette;
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use console::style;
+use http::{header::HOST, HeaderValue};
+use reqwest::{
+    redirect, tls, Certificate, Client, Identity, Method, NoProxy, Proxy, Request, Response,
+    ResponseBuilderExt, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+use url::Url;
+
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Clients by their settings, shared by all the requests of the process so that connections are
+/// reused, e.g. across the profiles of a batch run.
+static CLIENTS: LazyLock<Mutex<HashMap<ClientKey, Client>>> = LazyLock::new(Default::default);
+
+/// Addresses of the hosts with a `server_name`, looked up once per process so that the clients
+/// (keyed by these addresses) are reused.
+static SERVER_ADDRS: LazyLock<Mutex<HashMap<HostPort, Vec<SocketAddr>>>> =
+    LazyLock::new(Default::default);
+
+type HostPort = (String, u16);
+
+/// The settings, the user agent and the address overrides of the hosts.
+type ClientKey = (ClientConfig, String, Vec<(String, Vec<SocketAddr>)>);
+
+/// Settings of the HTTP client. They can be given for all the requests with a top level `client`
+/// section in the config file, and overridden by the `client` section of a request.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ClientConfig {
+    /// timeout for connecting to the server, in seconds
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub connect_timeout: Option<Duration>,
+    /// timeout for each read of the response, in seconds
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub read_timeout: Option<Duration>,
+    /// timeout for the whole request, from connecting until the body is received, in seconds
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub timeout: Option<Duration>,
+    /// how many times the request is retried on connect errors, and on 5xx and 429 responses if
+    /// its method is idempotent
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub retries: u32,
+    /// retry the requests with a non idempotent method (e.g. POST) on 5xx and 429 responses too
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub retry_non_idempotent: bool,
+    /// delay before the first retry in seconds (0.5 by default), doubled for each further retry.
+    /// A `retry-after` header in seconds takes precedence.
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub retry_backoff: Option<Duration>,
+    /// longest delay before a retry in seconds (5 by default), `retry-after` included
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_backoff: Option<Duration>,
+    /// maximum number of redirects to follow (10 by default), 0 to not follow redirects
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_redirects: Option<usize>,
+    /// use HTTP/2 without negotiating it first
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub http2_prior_knowledge: bool,
+    /// accept and decompress gzip responses, true by default
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub gzip: Option<bool>,
+    /// proxy for the requests: `http://`, `https://`, `socks5://` or `socks5h://` (the proxy
+    /// resolves the host), with optional `user:password@`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub proxy: Option<Url>,
+    /// hosts which aren't sent through the proxy, e.g. `localhost`, `.internal` or `10.0.0.0/8`
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub no_proxy: Vec<String>,
+    /// send the requests for `host:port` (or `host` for any port) to the address instead of
+    /// resolving the host, like curl's `--resolve`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub resolve: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub tls: TlsConfig,
+    /// record the responses to a file, or replay them from it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub cassette: Option<Cassette>,
+}
+
+/// TLS settings, e.g. for services on an internal PKI or behind mTLS gateways. The paths are
+/// relative to the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TlsConfig {
+    /// PEM files with extra root certificates to trust
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub ca_certs: Vec<PathBuf>,
+    /// client certificate for mTLS: a PEM file (which may contain the key as well), or a PKCS#12
+    /// file if its extension is `.p12` or `.pfx`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub client_cert: Option<PathBuf>,
+    /// PEM file with the private key of the client certificate
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub client_key: Option<PathBuf>,
+    /// password of the PKCS#12 file
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub client_cert_password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub min_tls_version: Option<TlsVersion>,
+    /// name used for SNI and to verify the server certificate instead of the url's host, which
+    /// is still where the request is sent to, and the request's host header.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub server_name: Option<String>,
+    /// don't verify the server certificate. Only for testing, the connection isn't secure.
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub insecure_skip_verify: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
+
+impl ClientConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let Some(proxy) = &self.proxy {
+            if !matches!(proxy.scheme(), "http" | "https" | "socks5" | "socks5h") {
+                return Err(anyhow!("unsupported proxy scheme: {}", proxy.scheme()));
+            }
+        }
+        for (host, addr) in &self.resolve {
+            parse_resolve(host, addr)?;
+        }
+        Ok(())
+    }
+
+    /// The `resolve` entries which apply to the host and port of the url.
+    pub(crate) fn resolve_overrides(&self, url: &Url) -> Result<Vec<(String, Vec<SocketAddr>)>> {
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            return Ok(vec![]);
+        };
+        let mut addrs = vec![];
+        for (key, addr) in &self.resolve {
+            let (name, name_port, ip) = parse_resolve(key, addr)?;
+            if name.eq_ignore_ascii_case(host) && name_port.is_none_or(|p| p == port) {
+                addrs.push(SocketAddr::new(ip, port));
+            }
+        }
+        Ok(match addrs.is_empty() {
+            true => vec![],
+            false => vec![(host.to_string(), addrs)],
+        })
+    }
+
+    /// Get the client with these settings, shared by all the requests of the process. `resolve`
+    /// overrides the addresses of the hosts.
+    pub(crate) fn client(
+        &self,
+        user_agent: &str,
+        resolve: Vec<(String, Vec<SocketAddr>)>,
+    ) -> Result<Client> {
+        let key = (self.clone(), user_agent.to_string(), resolve);
+        let mut clients = CLIENTS.lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let client = self.build(&key.1, &key.2)?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    fn build(&self, user_agent: &str, resolve: &[(String, Vec<SocketAddr>)]) -> Result<Client> {
+        let mut builder = Client::builder()
+            .user_agent(user_agent)
+            .gzip(self.gzip.unwrap_or(true));
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(max) = self.max_redirects {
+            builder = builder.redirect(match max {
+                0 => redirect::Policy::none(),
+                max => redirect::Policy::limited(max),
+            });
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        for (host, addrs) in resolve {
+            builder = builder.resolve_to_addrs(host, addrs);
+        }
+        if let Some(proxy) = &self.proxy {
+            let no_proxy = NoProxy::from_string(&self.no_proxy.join(","));
+            builder = builder.proxy(Proxy::all(proxy.as_str())?.no_proxy(no_proxy));
+        }
+
+        let tls = &self.tls;
+        for path in &tls.ca_certs {
+            for cert in Certificate::from_pem_bundle(&read(path)?)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(identity) = tls.identity()? {
+            builder = builder.identity(identity);
+        }
+        if let Some(version) = tls.min_tls_version {
+            builder = builder.min_tls_version(match version {
+                TlsVersion::Tls12 => tls::Version::TLS_1_2,
+                TlsVersion::Tls13 => tls::Version::TLS_1_3,
+            });
+        }
+        if tls.insecure_skip_verify {
+            eprintln!(
+                "{}",
+                style("WARNING: TLS certificate verification is disabled by insecure_skip_verify, the connection is NOT secure!")
+                    .red()
+                    .bold()
+            );
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Whether the request should be retried after `attempt` (0 for the first try) failed with
+    /// the result, and how long to wait before retrying. A connect error is always retried since
+    /// the request wasn't sent, an error response only if the method is idempotent.
+    pub(crate) fn retry_delay(
+        &self,
+        attempt: u32,
+        method: &Method,
+        result: &Result<Response>,
+    ) -> Option<Duration> {
+        if attempt >= self.retries {
+            return None;
+        }
+        let max_backoff = self.max_backoff.unwrap_or(DEFAULT_MAX_BACKOFF);
+        let backoff = self
+            .retry_backoff
+            .unwrap_or(DEFAULT_RETRY_BACKOFF)
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(max_backoff);
+        match result {
+            Err(e) if is_connect_error(e) => Some(backoff),
+            Ok(res)
+                if (res.status().is_server_error()
+                    || res.status() == StatusCode::TOO_MANY_REQUESTS)
+                    && (method.is_idempotent() || self.retry_non_idempotent) =>
+            {
+                let retry_after = res
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .map(Duration::from_secs);
+                Some(retry_after.map_or(backoff, |d| d.min(max_backoff)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl TlsConfig {
+    /// Make the relative file paths relative to `dir`.
+    pub(crate) fn resolve_paths(&mut self, dir: &Path) {
+        let paths = self
+            .ca_certs
+            .iter_mut()
+            .chain(self.client_cert.as_mut())
+            .chain(self.client_key.as_mut());
+        for path in paths {
+            if path.is_relative() {
+                *path = dir.join(&path);
+            }
+        }
+    }
+
+    /// With a `server_name`, the name and the addresses it should resolve to, i.e. the ones of
+    /// the url's host: from `resolve` if it's overridden there, or from DNS (once per process).
+    /// The request is then sent to the url with the name as host, see `route_to_server_name`.
+    pub(crate) async fn server_name_override(
+        &self,
+        url: &Url,
+        resolve: &[(String, Vec<SocketAddr>)],
+    ) -> Result<Option<(String, Vec<SocketAddr>)>> {
+        let Some(name) = &self.server_name else {
+            return Ok(None);
+        };
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("server_name requires a host in the url"))?;
+        if let Some((_, addrs)) = resolve.iter().find(|(h, _)| h == host) {
+            return Ok(Some((name.clone(), addrs.clone())));
+        }
+
+        let key = (host.to_string(), url.port_or_known_default().unwrap_or(443));
+        let cached = SERVER_ADDRS.lock().unwrap().get(&key).cloned();
+        let addrs = match cached {
+            Some(addrs) => addrs,
+            None => {
+                let addrs: Vec<_> =
+                    tokio::net::lookup_host((key.0.trim_matches(['[', ']']), key.1))
+                        .await?
+                        .collect();
+                SERVER_ADDRS.lock().unwrap().insert(key, addrs.clone());
+                addrs
+            }
+        };
+        Ok(Some((name.clone(), addrs)))
+    }
+
+    /// The client certificate and key, from PEM files or a PKCS#12 file.
+    fn identity(&self) -> Result<Option<Identity>> {
+        let Some(cert) = &self.client_cert else {
+            return Ok(None);
+        };
+        let is_pkcs12 = matches!(
+            cert.extension().and_then(|ext| ext.to_str()),
+            Some("p12" | "pfx")
+        );
+        let mut pem = if is_pkcs12 {
+            pkcs12_to_pem(
+                &read(cert)?,
+                self.client_cert_password.as_deref().unwrap_or_default(),
+            )
+            .map_err(|e| anyhow!("invalid PKCS#12 file {}: {}", cert.display(), e))?
+        } else {
+            read(cert)?
+        };
+        if let Some(key) = &self.client_key {
+            pem.push(b'\n');
+            pem.extend(read(key)?);
+        }
+        Ok(Some(Identity::from_pem(&pem)?))
+    }
+}
+
+/// Errors from connecting to the server, or to the unix socket.
+fn is_connect_error(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<reqwest::Error>() {
+        Some(e) => e.is_connect(),
+        None => e.is::<std::io::Error>(),
+    }
+}
+
+/// Send the request to the `server_name` instead of the url's host, so that the connection (SNI
+/// and the certificate check) is for the name. The url's host is kept as the host header.
+pub(crate) fn route_to_server_name(req: &mut Request, name: &str) -> Result<()> {
+    if !req.headers().contains_key(HOST) {
+        let url = req.url();
+        let host = url.host_str().unwrap_or_default();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        req.headers_mut()
+            .insert(HOST, HeaderValue::from_str(&host)?);
+    }
+    req.url_mut().set_host(Some(name))?;
+    Ok(())
+}
+
+/// The response with the `server_name` in its url replaced by the host of the request's url.
+pub(crate) fn restore_host(res: Response, name: &str, url: &Url) -> Result<Response> {
+    if res.url().host_str() != Some(name) {
+        return Ok(res);
+    }
+    let mut original = res.url().clone();
+    original.set_host(url.host_str())?;
+    let (parts, body) = http::Response::from(res).into_parts();
+    let mut res = http::Response::builder().url(original).body(body)?;
+    *res.status_mut() = parts.status;
+    *res.version_mut() = parts.version;
+    *res.headers_mut() = parts.headers;
+    res.extensions_mut().extend(parts.extensions);
+    Ok(Response::from(res))
+}
+
+/// Parse a `resolve` entry into the host, the optional port and the address.
+fn parse_resolve(key: &str, addr: &str) -> Result<(String, Option<u16>, IpAddr)> {
+    let err = || {
+        anyhow!(
+            "invalid resolve entry {}: {}, expected host[:port]: address",
+            key,
+            addr
+        )
+    };
+    let (host, port) = match key.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && !host.ends_with(':') => {
+            (host, Some(port.parse().map_err(|_| err())?))
+        }
+        _ => (key, None),
+    };
+    let ip = addr.trim_matches(['[', ']']).parse().map_err(|_| err())?;
+    Ok((host.trim_matches(['[', ']']).to_string(), port, ip))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| anyhow!("failed to read {}: {}", path.display(), e))
+}
+
+/// Convert the key and certificate chain of a PKCS#12 file to PEM, since rustls only takes PEM.
+fn pkcs12_to_pem(der: &[u8], password: &str) -> Result<Vec<u8>> {
+    let store = p12_keystore::KeyStore::from_pkcs12(der, password)?;
+    let (_, chain) = store
+        .private_key_chain()
+        .ok_or_else(|| anyhow!("no private key found"))?;
+    let mut pem = to_pem("PRIVATE KEY", chain.key());
+    for cert in chain.chain() {
+        pem.push_str(&to_pem("CERTIFICATE", cert.as_der()));
+    }
+    Ok(pem.into_bytes())
+}
+
+fn to_pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_server::{Reply, Server},
+        RequestContext,
+    };
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_rustls::{
+        rustls::{crypto::ring, server::WebPkiClientVerifier, RootCertStore, ServerConfig},
+        TlsAcceptor,
+    };
+
+    /// An HTTPS server for `api.internal` which requires a client certificate signed by
+    /// the test CA.
+    async fn mtls_server() -> SocketAddr {
+        let pem = |name: &str| std::fs::read(format!("fixtures/tls/{}", name)).unwrap();
+        let certs = rustls_pemfile::certs(&mut &pem("server.pem")[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = rustls_pemfile::private_key(&mut &pem("server.key")[..])
+            .unwrap()
+            .unwrap();
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut &pem("ca.pem")[..]) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let provider = Arc::new(ring::default_provider());
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots.into(), provider.clone())
+            .build()
+            .unwrap();
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(|req| Reply::ok(req.lines(&["host:"])));
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    server.respond(stream).await;
+                }
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn resolve_overrides_should_match_host_and_port() {
+        let config: ClientConfig = serde_yaml::from_str(
+            "resolve:\n  api.example.com:443: 10.0.0.1\n  api.example.com:8080: 10.0.0.2\n  \
+             other.example.com: '[::1]'\n",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let overrides = |url: &str| config.resolve_overrides(&url.parse().unwrap()).unwrap();
+        assert_eq!(
+            overrides("https://api.example.com/a"),
+            vec![(
+                "api.example.com".to_string(),
+                vec!["10.0.0.1:443".parse().unwrap()]
+            )]
+        );
+        assert_eq!(
+            overrides("http://other.example.com:81/"),
+            vec![(
+                "other.example.com".to_string(),
+                vec!["[::1]:81".parse().unwrap()]
+            )]
+        );
+        assert_eq!(overrides("http://api.example.com/"), vec![]);
+
+        let config: ClientConfig = serde_yaml::from_str("resolve:\n  a:b: c\n").unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid resolve entry a:b: c, expected host[:port]: address"
+        );
+    }
+
+    #[test]
+    fn retry_delay_should_be_bounded() {
+        let config = ClientConfig {
+            retries: 100,
+            retry_backoff: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let err = Err(anyhow!(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused
+        )));
+        let secs = |secs| Some(Duration::from_secs(secs));
+        assert_eq!(config.retry_delay(2, &Method::POST, &err), secs(4));
+        assert_eq!(config.retry_delay(40, &Method::GET, &err), secs(5));
+
+        let unavailable = || {
+            let res = http::Response::builder()
+                .status(503)
+                .header(reqwest::header::RETRY_AFTER, "3600")
+                .body("")
+                .unwrap();
+            Ok(Response::from(res))
+        };
+        assert_eq!(config.retry_delay(0, &Method::GET, &unavailable()), secs(5));
+        assert_eq!(config.retry_delay(0, &Method::POST, &unavailable()), None);
+
+        let config = ClientConfig {
+            retry_non_idempotent: true,
+            max_backoff: Some(Duration::from_secs(60)),
+            ..config
+        };
+        assert_eq!(
+            config.retry_delay(0, &Method::POST, &unavailable()),
+            secs(60)
+        );
+    }
+
+    #[tokio::test]
+    async fn tls_config_should_support_mtls_and_server_name() {
+        let addr = mtls_server().await;
+        let url: Url = format!("https://{}/", addr).parse().unwrap();
+        let send = |tls: TlsConfig| {
+            let url = url.clone();
+            async move {
+                let config = ClientConfig {
+                    tls,
+                    ..Default::default()
+                };
+                let mut ctx: RequestContext = url.as_str().parse()?;
+                ctx.client = config;
+                let res = ctx.send().await?;
+                Ok::<_, anyhow::Error>((res.url().clone(), res.text().await?))
+            }
+        };
+
+        let tls = TlsConfig {
+            ca_certs: vec!["fixtures/tls/ca.pem".into()],
+            client_cert: Some("fixtures/tls/client.pem".into()),
+            client_key: Some("fixtures/tls/client.key".into()),
+            min_tls_version: Some(TlsVersion::Tls12),
+            server_name: Some("api.internal".into()),
+            ..Default::default()
+        };
+        // the original host is reported, and sent as the host header
+        let host = format!("host: {}", addr);
+        assert_eq!(
+            send(tls.clone()).await.unwrap(),
+            (url.clone(), host.clone())
+        );
+
+        let pkcs12 = TlsConfig {
+            client_cert: Some("fixtures/tls/client.p12".into()),
+            client_key: None,
+            client_cert_password: Some("xreq".into()),
+            ..tls.clone()
+        };
+        assert_eq!(send(pkcs12).await.unwrap().1, host);
+
+        // the server requires a client certificate
+        let no_cert = TlsConfig {
+            client_cert: None,
+            client_key: None,
+            ..tls.clone()
+        };
+        assert!(send(no_cert).await.is_err());
+
+        // the server certificate isn't issued by a trusted CA
+        let no_ca = TlsConfig {
+            ca_certs: vec![],
+            ..tls
+        };
+        assert!(send(no_ca).await.is_err());
+    }
+}
@@ -0,0 +1,606 @@
+use anyhow::{anyhow, Result};
+use serde_yaml::{Mapping, Value};
+use std::{collections::HashMap, env, path::Path};
+use tokio::fs;
+
+/// Options used when loading a config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// variables for the `{{ var }}` placeholders. They take precedence over the `vars` section of
+    /// the config file, which takes precedence over the environment variables.
+    pub vars: HashMap<String, String>,
+    /// environment used for request1 of the diff profiles with a single `request` template.
+    /// Takes precedence over the profile's `left`.
+    pub left: Option<String>,
+    /// environment used for request2 of the diff profiles with a single `request` template.
+    /// Takes precedence over the profile's `right`.
+    pub right: Option<String>,
+    /// only request1 is sent, e.g. for snapshots: the `request` templates are expanded with the
+    /// left environment for both requests, so no right environment is needed.
+    pub left_only: bool,
+}
+
+/// The kind of profiles in the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConfigKind {
+    /// each profile is a request
+    Request,
+    /// each profile has two requests, `request1` and `request2`
+    Diff,
+}
+
+impl LoadOptions {
+    pub fn new(vars: HashMap<String, String>) -> Self {
+        Self {
+            vars,
+            ..Default::default()
+        }
+    }
+}
+
+/// A diff profile whose `request` template couldn't be expanded, e.g. because no `left`
+/// environment is given. The error is only raised when the profile is run, so that the other
+/// profiles of the file can still be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InvalidProfile {
+    pub tags: Vec<String>,
+    pub error: String,
+}
+
+/// Read the config file and prepare the profiles for deserialization:
+/// - a profile with `extends: <profile>` is deep merged on top of that profile
+/// - (diff only) a profile with a single `request` template is expanded into `request1` and
+///   `request2` by applying the `left` and `right` environments of the top level `environments`
+/// - the top level `defaults` section is deep merged under each request
+/// - the `{{ var }}` placeholders are resolved
+///
+/// The top level `vars`, `defaults`, `client` and `environments` (diff only) keys are consumed,
+/// so they can't be used as profile names. The returned mapping only has the profiles, the diff
+/// profiles which couldn't be expanded are returned separately.
+pub(crate) async fn load_yaml(
+    path: &Path,
+    opts: &LoadOptions,
+    kind: ConfigKind,
+) -> Result<(Value, HashMap<String, InvalidProfile>)> {
+    let file = fs::read_to_string(path).await?;
+    let root: Value = serde_yaml::from_str(&file)?;
+    prepare(root, opts, kind)
+}
+
+fn prepare(
+    mut root: Value,
+    opts: &LoadOptions,
+    kind: ConfigKind,
+) -> Result<(Value, HashMap<String, InvalidProfile>)> {
+    let map = match &mut root {
+        Value::Mapping(map) => map,
+        Value::Null => return Ok((Value::Mapping(Mapping::new()), HashMap::new())),
+        _ => return Err(anyhow!("config file must be a mapping of profiles")),
+    };
+
+    let file_vars = match map.remove("vars") {
+        Some(vars) => to_vars(vars)?,
+        None => HashMap::new(),
+    };
+    let mut defaults = map.remove("defaults");
+    // the global client settings are defaults of every request
+    if let Some(client) = map.remove("client") {
+        let mut base = Mapping::new();
+        base.insert("client".into(), client);
+        let defaults = defaults.get_or_insert_with(|| Value::Mapping(Mapping::new()));
+        merge(defaults, &Value::Mapping(base));
+    }
+    let environments = match kind {
+        ConfigKind::Diff => map.remove("environments"),
+        ConfigKind::Request => None,
+    };
+    resolve_extends(map)?;
+
+    let lookup = |name: &str| {
+        opts.vars
+            .get(name)
+            .or_else(|| file_vars.get(name))
+            .cloned()
+            .or_else(|| env::var(name).ok())
+    };
+
+    let mut invalid = HashMap::new();
+    for (profile, value) in map.iter_mut() {
+        let name = profile.as_str().unwrap_or_default();
+        if kind == ConfigKind::Diff {
+            let tags = value
+                .get("tags")
+                .and_then(|tags| serde_yaml::from_value(tags.clone()).ok())
+                .unwrap_or_default();
+            if let Err(e) = expand_environments(value, environments.as_ref(), opts) {
+                let error = format!("{} in profile: {}", e, name);
+                invalid.insert(name.to_string(), InvalidProfile { tags, error });
+                continue;
+            }
+        }
+        if let Some(defaults) = &defaults {
+            match kind {
+                ConfigKind::Request => merge(value, defaults),
+                ConfigKind::Diff => {
+                    for key in ["request1", "request2"] {
+                        if let Some(request) = value.get_mut(key) {
+                            merge(request, defaults);
+                        }
+                    }
+                }
+            }
+        }
+        let requests = match kind {
+            ConfigKind::Request => vec![&mut *value],
+            ConfigKind::Diff => value
+                .as_mapping_mut()
+                .into_iter()
+                .flat_map(|m| m.iter_mut())
+                .filter(|(k, _)| {
+                    k.as_str()
+                        .is_some_and(|k| k == "request1" || k == "request2")
+                })
+                .map(|(_, v)| v)
+                .collect(),
+        };
+        for request in requests {
+            render_request(request, &lookup).map_err(|e| anyhow!("{} in profile: {}", e, name))?;
+        }
+    }
+    for name in invalid.keys() {
+        map.remove(name.as_str());
+    }
+
+    Ok((root, invalid))
+}
+
+/// Expand the `request` template of a diff profile into `request1` and `request2`. An environment
+/// may have a `base_url` which is prepended to relative request urls; the rest of it (headers,
+/// params, etc.) is deep merged under the request.
+fn expand_environments(
+    profile: &mut Value,
+    environments: Option<&Value>,
+    opts: &LoadOptions,
+) -> Result<()> {
+    let Some(profile) = profile.as_mapping_mut() else {
+        return Ok(());
+    };
+    let left = profile.remove("left");
+    let right = profile.remove("right");
+    let Some(request) = profile.remove("request") else {
+        return Ok(());
+    };
+    if profile.contains_key("request1") || profile.contains_key("request2") {
+        return Err(anyhow!("request can't be used with request1 or request2"));
+    }
+
+    let pick = |cli: &Option<String>, profile: Option<Value>, side: &str| match cli {
+        Some(name) => Ok(name.clone()),
+        None => match profile {
+            Some(Value::String(name)) => Ok(name),
+            _ => Err(anyhow!(
+                "no {} environment given, use --{} or set {} in the profile",
+                side,
+                side,
+                side
+            )),
+        },
+    };
+    let left = pick(&opts.left, left, "left")?;
+    let right = match opts.left_only {
+        true => left.clone(),
+        false => pick(&opts.right, right, "right")?,
+    };
+    let sides = [("request1", left), ("request2", right)];
+
+    for (key, env) in sides {
+        let mut env = environments
+            .and_then(|envs| envs.get(env.as_str()))
+            .cloned()
+            .ok_or_else(|| {
+                let available: Vec<_> = environments
+                    .and_then(|envs| envs.as_mapping())
+                    .map(|envs| envs.keys().filter_map(|k| k.as_str()).collect())
+                    .unwrap_or_default();
+                anyhow!(
+                    "environment {} not found. Available environments: {:?}.",
+                    env,
+                    available
+                )
+            })?;
+        let base_url = env.as_mapping_mut().and_then(|env| env.remove("base_url"));
+
+        let mut req = request.clone();
+        if let (Some(Value::String(base)), Some(Value::String(url))) =
+            (base_url, req.get_mut("url"))
+        {
+            if !url.contains("://") {
+                *url = format!(
+                    "{}/{}",
+                    base.trim_end_matches('/'),
+                    url.trim_start_matches('/')
+                );
+            }
+        }
+        merge(&mut req, &env);
+        profile.insert(Value::String(key.to_string()), req);
+    }
+    Ok(())
+}
+
+/// Replace every profile having an `extends` key with the profile it extends merged under it.
+fn resolve_extends(map: &mut Mapping) -> Result<()> {
+    let mut resolved = HashMap::new();
+    let names: Vec<String> = map
+        .keys()
+        .filter_map(|k| k.as_str().map(|s| s.to_string()))
+        .collect();
+    for name in &names {
+        resolve_profile(name, map, &mut resolved, &mut Vec::new())?;
+    }
+    for (name, value) in resolved {
+        map.insert(Value::String(name), value);
+    }
+    Ok(())
+}
+
+fn resolve_profile(
+    name: &str,
+    map: &Mapping,
+    resolved: &mut HashMap<String, Value>,
+    stack: &mut Vec<String>,
+) -> Result<Value> {
+    if let Some(value) = resolved.get(name) {
+        return Ok(value.clone());
+    }
+    if stack.iter().any(|n| n == name) {
+        stack.push(name.to_string());
+        return Err(anyhow!("cyclic extends: {}", stack.join(" -> ")));
+    }
+
+    let mut value = map[name].clone();
+    let parent = match value.as_mapping_mut().and_then(|m| m.remove("extends")) {
+        None => None,
+        Some(Value::String(parent)) => Some(parent),
+        Some(_) => {
+            return Err(anyhow!(
+                "extends must be a profile name in profile: {}",
+                name
+            ))
+        }
+    };
+    if let Some(parent) = parent {
+        if !map.contains_key(parent.as_str()) {
+            return Err(anyhow!(
+                "profile {} extends unknown profile {}",
+                name,
+                parent
+            ));
+        }
+        stack.push(name.to_string());
+        let base = resolve_profile(&parent, map, resolved, stack)?;
+        stack.pop();
+        merge(&mut value, &base);
+    }
+
+    resolved.insert(name.to_string(), value.clone());
+    Ok(value)
+}
+
+/// Deep merge `base` under `value`: mappings are merged recursively, any other value in `value`
+/// wins over the one in `base`.
+fn merge(value: &mut Value, base: &Value) {
+    if let (Value::Mapping(map), Value::Mapping(base)) = (value, base) {
+        for (k, v) in base {
+            match map.get_mut(k) {
+                Some(existing) => merge(existing, v),
+                None => {
+                    map.insert(k.clone(), v.clone());
+                }
+            }
+        }
+    }
+}
+
+fn to_vars(value: Value) -> Result<HashMap<String, String>> {
+    let vars: HashMap<String, Value> = serde_yaml::from_value(value)?;
+    vars.into_iter()
+        .map(|(k, v)| match v {
+            Value::String(s) => Ok((k, s)),
+            Value::Number(n) => Ok((k, n.to_string())),
+            Value::Bool(b) => Ok((k, b.to_string())),
+            _ => Err(anyhow!("variable {} must be a string, number or bool", k)),
+        })
+        .collect()
+}
+
+/// Replace the placeholders in the parts of a request which are sent, so that the other settings
+/// (e.g. the mask patterns of a diff) can use `{{` freely.
+fn render_request(request: &mut Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+    const RENDERED: [&str; 6] = ["url", "params", "headers", "body", "auth", "client"];
+    let Some(request) = request.as_mapping_mut() else {
+        return Ok(());
+    };
+    for (k, v) in request.iter_mut() {
+        if k.as_str().is_some_and(|k| RENDERED.contains(&k)) {
+            render(v, lookup)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replace the placeholders in all the string values (not the keys).
+fn render(value: &mut Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+    match value {
+        Value::String(s) => {
+            if s.contains("{{") {
+                *s = render_str(s, lookup)?;
+            }
+        }
+        Value::Sequence(seq) => {
+            for v in seq {
+                render(v, lookup)?;
+            }
+        }
+        Value::Mapping(map) => {
+            for (_, v) in map.iter_mut() {
+                render(v, lookup)?;
+            }
+        }
+        Value::Tagged(tagged) => render(&mut tagged.value, lookup)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+fn render_str(s: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("{{") {
+        // `{{{{` is a literal `{{`
+        if rest[start..].starts_with("{{{{") {
+            result.push_str(&rest[..start + 2]);
+            rest = &rest[start + 4..];
+            continue;
+        }
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| anyhow!("unclosed placeholder in {:?}", s))?;
+        let name = rest[start + 2..start + end].trim();
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+        if !valid {
+            return Err(anyhow!("invalid variable name {:?} in {:?}", name, s));
+        }
+        let val = lookup(name).ok_or_else(|| anyhow!("unresolved variable: {}", name))?;
+        result.push_str(&rest[..start]);
+        result.push_str(&val);
+        rest = &rest[start + end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_str_should_replace_placeholders() {
+        let lookup = |name: &str| match name {
+            "host" => Some("example.com".to_string()),
+            "id" => Some("42".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            render_str("https://{{host}}/items/{{ id }}?x={{id}}", &lookup).unwrap(),
+            "https://example.com/items/42?x=42"
+        );
+        assert_eq!(render_str("no vars", &lookup).unwrap(), "no vars");
+
+        let err = render_str("https://{{ nope }}/", &lookup).unwrap_err();
+        assert_eq!(err.to_string(), "unresolved variable: nope");
+        assert!(render_str("https://{{ host /", &lookup).is_err());
+        assert!(render_str("{{ a b }}", &lookup).is_err());
+        assert_eq!(
+            render_str("{{{{ id }} is {{ id }}", &lookup).unwrap(),
+            "{{ id }} is 42"
+        );
+    }
+
+    #[test]
+    fn prepare_should_merge_extends_and_defaults() {
+        let root: Value = serde_yaml::from_str(
+            r#"
+defaults:
+  headers:
+    user-agent: xdiff
+    authorization: Bearer x
+base:
+  url: https://example.com/a
+  params:
+    page: 1
+    size: 10
+child:
+  extends: base
+  params:
+    size: 20
+  headers:
+    authorization: Bearer y
+grandchild:
+  extends: child
+  url: https://example.com/b
+"#,
+        )
+        .unwrap();
+        let (root, _) = prepare(root, &LoadOptions::default(), ConfigKind::Request).unwrap();
+        let expected: Value = serde_yaml::from_str(
+            r#"
+url: https://example.com/b
+params:
+  page: 1
+  size: 20
+headers:
+  user-agent: xdiff
+  authorization: Bearer y
+"#,
+        )
+        .unwrap();
+        assert_eq!(root["grandchild"], expected);
+        assert_eq!(root["base"]["headers"]["authorization"], "Bearer x");
+        assert!(root.get("defaults").is_none());
+
+        let root: Value = serde_yaml::from_str(
+            r#"
+a:
+  extends: b
+b:
+  extends: c
+c:
+  extends: a
+"#,
+        )
+        .unwrap();
+        let err = prepare(root, &LoadOptions::default(), ConfigKind::Request).unwrap_err();
+        assert!(err.to_string().starts_with("cyclic extends: "));
+
+        let root: Value = serde_yaml::from_str("a:\n  extends: nope\n").unwrap();
+        let err = prepare(root, &LoadOptions::default(), ConfigKind::Request).unwrap_err();
+        assert_eq!(err.to_string(), "profile a extends unknown profile nope");
+    }
+
+    #[test]
+    fn prepare_should_apply_defaults_to_requests() {
+        let root: Value = serde_yaml::from_str(
+            r#"
+client:
+  timeout: 5
+  retries: 2
+defaults:
+  headers:
+    user-agent: xdiff
+todo:
+  request1:
+    url: https://example.com/a
+  request2:
+    url: https://example.com/b
+    client:
+      retries: 0
+"#,
+        )
+        .unwrap();
+        let (root, _) = prepare(root, &LoadOptions::default(), ConfigKind::Diff).unwrap();
+        assert_eq!(root["todo"]["request1"]["headers"]["user-agent"], "xdiff");
+        assert_eq!(root["todo"]["request2"]["headers"]["user-agent"], "xdiff");
+        assert_eq!(root["todo"]["request1"]["client"]["retries"], 2);
+        assert_eq!(root["todo"]["request2"]["client"]["retries"], 0);
+        assert_eq!(root["todo"]["request2"]["client"]["timeout"], 5);
+        assert!(root["todo"].get("headers").is_none());
+    }
+
+    #[test]
+    fn prepare_should_only_render_requests() {
+        let root: Value = serde_yaml::from_str(
+            r#"
+vars:
+  id: 1
+todo:
+  request1:
+    url: https://example.com/{{ id }}
+    body: "{{{{ raw }}"
+  request2:
+    url: https://example.com/{{ id }}
+  response:
+    mask:
+      - pattern: '\{{2}[a-z]+\}{2}'
+"#,
+        )
+        .unwrap();
+        let (root, _) = prepare(root, &LoadOptions::default(), ConfigKind::Diff).unwrap();
+        assert_eq!(root["todo"]["request1"]["url"], "https://example.com/1");
+        assert_eq!(root["todo"]["request1"]["body"], "{{ raw }}");
+        assert_eq!(
+            root["todo"]["response"]["mask"][0]["pattern"],
+            r"\{{2}[a-z]+\}{2}"
+        );
+    }
+
+    #[test]
+    fn prepare_should_expand_environments() {
+        let root: Value = serde_yaml::from_str(
+            r#"
+environments:
+  prod:
+    base_url: https://api.example.com/
+    headers:
+      authorization: Bearer prod
+  canary:
+    base_url: https://canary.example.com
+search:
+  left: prod
+  right: prod
+  request:
+    url: /search
+    params:
+      q: rust
+"#,
+        )
+        .unwrap();
+        let opts = LoadOptions {
+            right: Some("canary".into()),
+            ..Default::default()
+        };
+        let (root, _) = prepare(root, &opts, ConfigKind::Diff).unwrap();
+        let expected: Value = serde_yaml::from_str(
+            r#"
+request1:
+  url: https://api.example.com/search
+  params:
+    q: rust
+  headers:
+    authorization: Bearer prod
+request2:
+  url: https://canary.example.com/search
+  params:
+    q: rust
+"#,
+        )
+        .unwrap();
+        assert_eq!(root["search"], expected);
+    }
+
+    #[test]
+    fn invalid_template_should_not_affect_other_profiles() {
+        let root: Value = serde_yaml::from_str(
+            r#"
+environments:
+  prod:
+    base_url: https://api.example.com
+search:
+  tags: [smoke]
+  request:
+    url: /search
+todo:
+  request1:
+    url: https://example.com/a
+  request2:
+    url: https://example.com/b
+"#,
+        )
+        .unwrap();
+        let (root, invalid) = prepare(root, &LoadOptions::default(), ConfigKind::Diff).unwrap();
+        assert_eq!(root["todo"]["request2"]["url"], "https://example.com/b");
+        assert!(root.get("search").is_none());
+        assert_eq!(
+            invalid["search"],
+            InvalidProfile {
+                tags: vec!["smoke".into()],
+                error: "no left environment given, use --left or set left in the profile in \
+                        profile: search"
+                    .into()
+            }
+        );
+    }
+}
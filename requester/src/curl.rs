@@ -0,0 +1,581 @@
+use crate::{
+    auth::{Auth, Secret},
+    body::BodyType,
+    req::{append_param, RequestContext},
+};
+use anyhow::{anyhow, Result};
+use console::style;
+use http::{
+    header::{HeaderName, ACCEPT, CONTENT_TYPE, COOKIE, REFERER},
+    HeaderValue, Method,
+};
+use serde_json::{Map, Value};
+use std::{iter::Peekable, path::PathBuf, str::Chars, time::Duration};
+
+/// The environment variable which the password of `-u` is read from.
+const PASSWORD_ENV: &str = "XREQ_PASSWORD";
+
+/// Options which take a value, by their short and long names.
+const VALUE_OPTIONS: &[(Option<char>, &str)] = &[
+    (Some('X'), "request"),
+    (Some('H'), "header"),
+    (Some('d'), "data"),
+    (None, "data-raw"),
+    (None, "data-binary"),
+    (None, "data-ascii"),
+    (None, "data-urlencode"),
+    (None, "json"),
+    (Some('F'), "form"),
+    (None, "form-string"),
+    (Some('u'), "user"),
+    (Some('b'), "cookie"),
+    (Some('A'), "user-agent"),
+    (Some('e'), "referer"),
+    (Some('x'), "proxy"),
+    (Some('m'), "max-time"),
+    (None, "connect-timeout"),
+    (None, "max-redirs"),
+    (None, "url"),
+    (None, "unix-socket"),
+    (None, "resolve"),
+    (None, "cacert"),
+    (Some('E'), "cert"),
+    (None, "key"),
+    (Some('o'), "output"),
+];
+
+/// Flags, by their short and long names.
+const FLAGS: &[(Option<char>, &str)] = &[
+    (Some('G'), "get"),
+    (Some('I'), "head"),
+    (Some('k'), "insecure"),
+    (None, "http2-prior-knowledge"),
+    // the ones below don't change the request
+    (None, "compressed"),
+    (Some('L'), "location"),
+    (Some('s'), "silent"),
+    (Some('S'), "show-error"),
+    (Some('v'), "verbose"),
+    (Some('i'), "include"),
+    (Some('f'), "fail"),
+    (Some('g'), "globoff"),
+    (Some('N'), "no-buffer"),
+    (None, "http1.1"),
+    (None, "http2"),
+];
+
+/// Parse a curl command into a request. The options which only affect how curl prints the
+/// response are ignored, an unsupported option is an error.
+pub(crate) fn parse(cmd: &str) -> Result<RequestContext> {
+    let mut words = split_words(cmd)?.into_iter().peekable();
+    if words.peek().map(String::as_str) == Some("curl") {
+        words.next();
+    }
+
+    let mut curl = Curl::default();
+    while let Some(word) = words.next() {
+        if let Some(name) = word.strip_prefix("--") {
+            let value = match VALUE_OPTIONS.iter().any(|(_, long)| *long == name) {
+                true => Some(next_value(&mut words, &word)?),
+                false => None,
+            };
+            curl.apply(name, value)?;
+        } else if word.len() > 1 && word.starts_with('-') {
+            // short options may be combined, e.g. `-sSL`, and a value may be attached, e.g. `-XPUT`
+            for (i, c) in word[1..].char_indices() {
+                if let Some((_, long)) = VALUE_OPTIONS.iter().find(|(short, _)| *short == Some(c)) {
+                    let rest = &word[i + 1 + c.len_utf8()..];
+                    let value = match rest.is_empty() {
+                        true => next_value(&mut words, &word)?,
+                        false => rest.to_string(),
+                    };
+                    curl.apply(long, Some(value))?;
+                    break;
+                }
+                match FLAGS.iter().find(|(short, _)| *short == Some(c)) {
+                    Some((_, long)) => curl.apply(long, None)?,
+                    None => return Err(anyhow!("unsupported curl option: -{}", c)),
+                }
+            }
+        } else {
+            curl.url = Some(word);
+        }
+    }
+    curl.into_request()
+}
+
+/// Add a multipart field. curl sends every part of a repeated name, so they're collected into an
+/// array.
+fn add_field(form: &mut Map<String, Value>, name: &str, value: Value) {
+    match form.get_mut(name) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+        None => {
+            form.insert(name.to_string(), value);
+        }
+    }
+}
+
+/// Split the `;type=` and `;filename=` modifiers off a `-F` value, e.g.
+/// `@avatar.png;type=image/png`, into the `content_type` and `filename` of the file part.
+fn split_form_modifiers(value: &str) -> (&str, Vec<(&'static str, &str)>) {
+    let mut value = value;
+    let mut modifiers = vec![];
+    while let Some((rest, modifier)) = value.rsplit_once(';') {
+        match modifier.trim().split_once('=') {
+            Some(("type", v)) => modifiers.push(("content_type", v)),
+            Some(("filename", v)) => modifiers.push(("filename", v.trim_matches('"'))),
+            _ => break,
+        }
+        value = rest;
+    }
+    (value, modifiers)
+}
+
+/// The path of a `@file` relative to the current directory, so that it still refers to the same
+/// file from the generated config file.
+fn absolute(path: &str) -> Result<PathBuf> {
+    std::path::absolute(path).map_err(|e| anyhow!("invalid curl file path {}: {}", path, e))
+}
+
+fn next_value(words: &mut impl Iterator<Item = String>, option: &str) -> Result<String> {
+    words
+        .next()
+        .ok_or_else(|| anyhow!("missing value for curl option {}", option))
+}
+
+/// The request as the options are parsed, since the url may come after them.
+#[derive(Debug, Default)]
+struct Curl {
+    url: Option<String>,
+    method: Option<Method>,
+    head: bool,
+    get: bool,
+    json: bool,
+    data: Vec<String>,
+    data_file: Option<PathBuf>,
+    form: Map<String, Value>,
+    ctx: Option<RequestContext>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    cookies: Vec<String>,
+}
+
+impl Curl {
+    fn apply(&mut self, name: &str, value: Option<String>) -> Result<()> {
+        // the settings are kept in a request which gets its url at the end
+        let ctx = self
+            .ctx
+            .get_or_insert_with(|| "http://localhost/".parse().unwrap());
+        let value = value.unwrap_or_default();
+        match name {
+            "request" => self.method = Some(value.to_uppercase().parse()?),
+            "header" => {
+                let (name, value) = value
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("invalid curl header: {}", value))?;
+                let value = value.trim();
+                // `Name:` removes a header curl would send, there's nothing to remove here
+                if !value.is_empty() {
+                    self.headers
+                        .push((name.trim().parse()?, HeaderValue::from_str(value)?));
+                }
+            }
+            "data" | "data-binary" | "data-ascii" => match value.strip_prefix('@') {
+                Some(path) => self.data_file = Some(absolute(path)?),
+                None => self.data.push(value),
+            },
+            "data-raw" => self.data.push(value),
+            "data-urlencode" => self.data.push(urlencode_data(&value)?),
+            "json" => {
+                self.json = true;
+                match value.strip_prefix('@') {
+                    Some(path) => self.data_file = Some(absolute(path)?),
+                    None => self.data.push(value),
+                }
+            }
+            "form" => {
+                let (name, value) = value
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("invalid curl form field: {}", value))?;
+                let (value, modifiers) = split_form_modifiers(value);
+                let value = match value.strip_prefix('@') {
+                    Some(path) => {
+                        let mut file = Map::new();
+                        file.insert("file".into(), absolute(path)?.to_string_lossy().into());
+                        for (key, value) in modifiers {
+                            file.insert(key.into(), value.into());
+                        }
+                        Value::Object(file)
+                    }
+                    None if modifiers.is_empty() => Value::String(value.to_string()),
+                    None => {
+                        return Err(anyhow!(
+                            "curl form field {} can only have a type or filename with a file",
+                            name
+                        ))
+                    }
+                };
+                add_field(&mut self.form, name, value);
+            }
+            "form-string" => {
+                let (name, value) = value
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("invalid curl form field: {}", value))?;
+                add_field(&mut self.form, name, Value::String(value.to_string()));
+            }
+            "user" => {
+                let (username, password) = match value.split_once(':') {
+                    Some((username, password)) => (username, Some(password)),
+                    None => (value.as_str(), None),
+                };
+                // the password isn't written into the config file, it's read when the request is sent
+                if password.is_some() {
+                    eprintln!(
+                        "{}",
+                        style(format!(
+                            "NOTE: the password of curl -u isn't kept, set the {} environment variable to send it",
+                            PASSWORD_ENV
+                        ))
+                        .yellow()
+                    );
+                }
+                ctx.auth = Some(Auth::Basic {
+                    username: username.to_string(),
+                    password: password.map(|_| Secret::Env {
+                        env: PASSWORD_ENV.to_string(),
+                    }),
+                });
+            }
+            "cookie" if value.contains('=') => self.cookies.push(value),
+            "cookie" => return Err(anyhow!("curl cookie files are not supported: {}", value)),
+            "user-agent" => ctx.user_agent = Some(value),
+            "referer" => self.headers.push((REFERER, HeaderValue::from_str(&value)?)),
+            "proxy" => {
+                let proxy = match value.contains("://") {
+                    true => value,
+                    false => format!("http://{}", value),
+                };
+                ctx.client.proxy = Some(proxy.parse()?);
+            }
+            "max-time" => ctx.client.timeout = Some(parse_secs(&value)?),
+            "connect-timeout" => ctx.client.connect_timeout = Some(parse_secs(&value)?),
+            "max-redirs" => {
+                // a negative number is unlimited in curl
+                ctx.client.max_redirects = value.parse::<i64>()?.try_into().ok();
+            }
+            "url" => self.url = Some(value),
+            "unix-socket" => ctx.unix_socket = Some(PathBuf::from(value)),
+            "resolve" => {
+                // host:port:addr
+                let mut parts = value.splitn(3, ':');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(host), Some(port), Some(addr)) if !addr.is_empty() => {
+                        let addr = addr.to_string();
+                        ctx.client
+                            .resolve
+                            .insert(format!("{}:{}", host, port), addr);
+                    }
+                    _ => return Err(anyhow!("invalid curl resolve: {}", value)),
+                }
+            }
+            "cacert" => ctx.client.tls.ca_certs.push(PathBuf::from(value)),
+            "cert" => ctx.client.tls.client_cert = Some(PathBuf::from(value)),
+            "key" => ctx.client.tls.client_key = Some(PathBuf::from(value)),
+            "get" => self.get = true,
+            "head" => self.head = true,
+            "insecure" => ctx.client.tls.insecure_skip_verify = true,
+            "http2-prior-knowledge" => ctx.client.http2_prior_knowledge = true,
+            name if FLAGS.iter().any(|(_, long)| *long == name) => {}
+            "output" => {}
+            name => return Err(anyhow!("unsupported curl option: --{}", name)),
+        }
+        Ok(())
+    }
+
+    fn into_request(self) -> Result<RequestContext> {
+        let url = self
+            .url
+            .ok_or_else(|| anyhow!("no url in the curl command"))?;
+        // curl defaults to http for urls without a scheme
+        let url = match url.contains("://") {
+            true => url,
+            false => format!("http://{}", url),
+        };
+        let parsed: RequestContext = url.parse()?;
+        let mut ctx = self.ctx.unwrap_or_else(|| parsed.clone());
+        ctx.url = parsed.url;
+        ctx.params = parsed.params;
+        for (name, value) in self.headers {
+            ctx.headers.append(name, value);
+        }
+        if !self.cookies.is_empty() {
+            ctx.headers
+                .insert(COOKIE, HeaderValue::from_str(&self.cookies.join("; "))?);
+        }
+
+        let has_data = !self.data.is_empty() || self.data_file.is_some();
+        if has_data && !self.form.is_empty() {
+            return Err(anyhow!("curl data and form options can't be used together"));
+        }
+        ctx.method = match self.method {
+            Some(method) => method,
+            None if self.head => Method::HEAD,
+            None if (has_data && !self.get) || !self.form.is_empty() => Method::POST,
+            None => Method::GET,
+        };
+
+        let data = self.data.join("&");
+        if self.get {
+            if self.data_file.is_some() {
+                return Err(anyhow!("curl --get can't send a data file"));
+            }
+            for (k, v) in url::form_urlencoded::parse(data.as_bytes()) {
+                append_param(&mut ctx.params, &k, &v);
+            }
+        } else if !self.form.is_empty() {
+            ctx.body_type = Some(BodyType::Multipart);
+            ctx.body = Some(Value::Object(self.form));
+        } else if has_data {
+            if self.json {
+                if !ctx.headers.contains_key(ACCEPT) {
+                    ctx.headers
+                        .insert(ACCEPT, HeaderValue::from_static("application/json"));
+                }
+                if !ctx.headers.contains_key(CONTENT_TYPE) {
+                    ctx.headers
+                        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                }
+            } else if !ctx.headers.contains_key(CONTENT_TYPE) {
+                ctx.headers.insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("application/x-www-form-urlencoded"),
+                );
+            }
+            match self.data_file {
+                Some(path) if self.data.is_empty() => ctx.body_file = Some(path),
+                Some(_) => return Err(anyhow!("curl data from a file can't be combined")),
+                None => match ctx.body_type() {
+                    BodyType::Json => match serde_json::from_str(&data) {
+                        Ok(json) => ctx.body = Some(json),
+                        // sent as is, the content-type header is kept
+                        Err(_) => {
+                            ctx.body_type = Some(BodyType::Raw);
+                            ctx.body = Some(Value::String(data));
+                        }
+                    },
+                    _ => ctx.body = Some(Value::String(data)),
+                },
+            }
+        }
+        Ok(ctx)
+    }
+}
+
+/// Encode a `--data-urlencode` value: `content`, `=content` or `name=content`.
+fn urlencode_data(value: &str) -> Result<String> {
+    let encode = |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+    match value.split_once('=') {
+        Some(("", content)) => Ok(encode(content)),
+        Some((name, content)) => Ok(format!("{}={}", name, encode(content))),
+        None if value.contains('@') => Err(anyhow!(
+            "curl --data-urlencode from a file is not supported: {}",
+            value
+        )),
+        None => Ok(encode(value)),
+    }
+}
+
+fn parse_secs(value: &str) -> Result<Duration> {
+    Ok(Duration::try_from_secs_f64(value.parse()?)?)
+}
+
+/// Split a shell command into words, handling single, double and `$'...'` quotes, escapes and
+/// line continuations the way bash does.
+fn split_words(cmd: &str) -> Result<Vec<String>> {
+    let unterminated = || anyhow!("unterminated quote in the curl command");
+    let mut words = Vec::new();
+    // `None` until the word starts, so that `''` is an empty word
+    let mut word: Option<String> = None;
+    let mut chars = cmd.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => {}
+            },
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or_else(unterminated)? {
+                            '\n' => {}
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        '\\' => word.push(unescape(&mut chars)?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// The character of an escape sequence in a `$'...'` string, after the backslash.
+fn unescape(chars: &mut Peekable<Chars>) -> Result<char> {
+    Ok(match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('0') => '\0',
+        Some('x') => code_point(chars, 2)?,
+        Some('u') => code_point(chars, 4)?,
+        Some('U') => code_point(chars, 8)?,
+        Some(c) => c,
+        None => return Err(anyhow!("unterminated quote in the curl command")),
+    })
+}
+
+/// The character of up to `len` hex digits.
+fn code_point(chars: &mut Peekable<Chars>, len: usize) -> Result<char> {
+    let hex: String = std::iter::from_fn(|| chars.next_if(char::is_ascii_hexdigit))
+        .take(len)
+        .collect();
+    u32::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| anyhow!("invalid escape sequence in the curl command"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_words_should_handle_quotes() {
+        let cmd = "curl 'https://api.example.com/a b' \\\n  -H \"x-name: \\\"q\\\"\" --data-raw $'{\"a\":\"\\u00e9\\n\"}' -d ''";
+        assert_eq!(
+            split_words(cmd).unwrap(),
+            vec![
+                "curl",
+                "https://api.example.com/a b",
+                "-H",
+                "x-name: \"q\"",
+                "--data-raw",
+                "{\"a\":\"é\n\"}",
+                "-d",
+                ""
+            ]
+        );
+        assert!(split_words("curl 'https://api.example.com").is_err());
+    }
+
+    #[test]
+    fn parse_should_convert_curl_options() {
+        let ctx = RequestContext::from_curl(
+            r#"curl 'https://api.example.com/orders?page=2' -X PUT \
+              -H 'Content-Type: application/json' -H 'x-api-version: 3' \
+              -b 'session=abc' -b 'theme=dark' -u alice:secret \
+              --data-raw '{"id":1}' --compressed -sSL -m 2.5 -k"#,
+        )
+        .unwrap();
+        assert_eq!(ctx.method, Method::PUT);
+        assert_eq!(ctx.url.as_str(), "https://api.example.com/orders");
+        assert_eq!(ctx.params, serde_json::json!({"page": "2"}));
+        assert_eq!(ctx.headers["x-api-version"], "3");
+        assert_eq!(ctx.headers[COOKIE], "session=abc; theme=dark");
+        assert_eq!(ctx.body, Some(serde_json::json!({"id": 1})));
+        assert_eq!(
+            ctx.auth,
+            Some(Auth::Basic {
+                username: "alice".into(),
+                password: Some(Secret::Env {
+                    env: "XREQ_PASSWORD".into()
+                })
+            })
+        );
+        assert_eq!(ctx.client.timeout, Some(Duration::from_millis(2500)));
+        assert!(ctx.client.tls.insecure_skip_verify);
+
+        let ctx = RequestContext::from_curl(
+            "curl -d a=1 --data-urlencode 'q=hello world' example.com/search",
+        )
+        .unwrap();
+        assert_eq!(ctx.method, Method::POST);
+        assert_eq!(ctx.url.as_str(), "http://example.com/search");
+        assert_eq!(
+            ctx.headers[CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(ctx.body, Some(Value::String("a=1&q=hello+world".into())));
+
+        let ctx = RequestContext::from_curl(
+            "curl -G -d a=1 --data-urlencode 'q=hello world' 'http://example.com/search?a=0'",
+        )
+        .unwrap();
+        assert_eq!(ctx.method, Method::GET);
+        assert_eq!(
+            ctx.params,
+            serde_json::json!({"a": ["0", "1"], "q": "hello world"})
+        );
+        assert_eq!(ctx.body, None);
+
+        let ctx = RequestContext::from_curl(
+            "curl -XPOST -F name=req -F file=@fixtures/req.yml \
+              -F 'file=@fixtures/diff.yml;type=text/yaml;filename=\"b.yml\"' \
+              --form-string 'file=inline;type=x' http://localhost/upload",
+        )
+        .unwrap();
+        let dir = std::env::current_dir().unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        assert_eq!(ctx.body_type, Some(BodyType::Multipart));
+        assert_eq!(
+            ctx.body,
+            Some(serde_json::json!({
+                "name": "req",
+                "file": [
+                    {"file": path("fixtures/req.yml")},
+                    {"file": path("fixtures/diff.yml"), "content_type": "text/yaml", "filename": "b.yml"},
+                    "inline;type=x"
+                ]
+            }))
+        );
+        assert!(
+            RequestContext::from_curl("curl -F 'a=1;type=text/plain' http://localhost/").is_err()
+        );
+
+        let err = RequestContext::from_curl("curl --trace out.txt http://localhost/").unwrap_err();
+        assert_eq!(err.to_string(), "unsupported curl option: --trace");
+    }
+}
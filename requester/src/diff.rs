@@ -1,57 +1,263 @@
-use crate::req::RequestContext;
+mod json;
+mod report;
+mod snapshot;
+
+use crate::{
+    config::{self, ConfigKind, InvalidProfile},
+    jsonpath::JsonPath,
+    req::{AttemptStart, RequestContext},
+    LoadOptions,
+};
 use anyhow::Result;
-use console::{style, Style};
+use regex::Regex;
 use reqwest::Response;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use similar::{ChangeTag, TextDiff};
-use std::{collections::HashMap, fmt, io::Write, path::Path};
-use tokio::fs;
+use std::{collections::HashMap, path::Path, time::Instant};
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub use json::JsonChange;
+pub use report::{
+    BodyDiff, DiffLine, DiffReport, HeaderChange, Hunk, LatencyRegression, LineTag, StatusChange,
+    Timing,
+};
+pub use snapshot::Snapshot;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct DiffConfig {
     #[serde(flatten)]
     ctxs: HashMap<String, DiffContext>,
+    /// the profiles which couldn't be expanded, with the error raised when they're run
+    #[serde(skip)]
+    invalid: HashMap<String, InvalidProfile>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct DiffContext {
     pub request1: RequestContext,
     pub request2: RequestContext,
     #[serde(skip_serializing_if = "is_default_response", default)]
     pub response: ResponseContext,
+    /// tags to select a group of profiles to run together
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub tags: Vec<String>,
 }
 
 fn is_default_response(r: &ResponseContext) -> bool {
     r == &ResponseContext::default()
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct ResponseContext {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub skip_headers: Vec<String>,
+    /// JSONPath expressions of body fields to remove before comparing, e.g. `$..trace_id`
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub skip_body: Vec<String>,
+    /// arrays whose element order doesn't matter
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub unordered_arrays: Vec<ArrayRule>,
+    /// relaxed comparison rules for body fields
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub compare: Vec<CompareRule>,
+    /// replace values matching the regexes in headers and body before comparing
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub mask: Vec<MaskRule>,
+    /// flag a latency regression when request2 is slower than request1 by more than this percent
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub latency_threshold: Option<f64>,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub diff_mode: DiffMode,
+}
+
+/// Replace the matches of `pattern` with `replacement`, which may refer to capture groups
+/// like `$1` or `${name}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MaskRule {
+    pub pattern: String,
+    #[serde(default = "default_mask_replacement")]
+    pub replacement: String,
+}
+
+fn default_mask_replacement() -> String {
+    "<masked>".to_string()
+}
+
+/// Compare the arrays at `path` as multisets, or pair up their elements by the `key` member.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArrayRule {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub key: Option<String>,
+}
+
+/// Relaxed equality for the body fields at `path`. When several rules match a field they are
+/// combined.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CompareRule {
+    pub path: String,
+    /// numbers are equal if they differ by no more than this
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub tolerance: Option<f64>,
+    /// numbers are equal if they differ by no more than this fraction of the larger one
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub relative_tolerance: Option<f64>,
+    /// strings are compared with numbers and booleans by their value, e.g. `"1" == 1`
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub coerce_types: bool,
+    /// strings are compared case-insensitively
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub ignore_case: bool,
+    /// a `null` value is equal to a missing key
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub null_as_missing: bool,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// How response bodies are compared.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffMode {
+    /// pretty-print the body and compare it line by line
+    #[default]
+    Text,
+    /// compare JSON bodies structurally and report changes by JSON path.
+    /// Falls back to text when either body isn't valid JSON.
+    Json,
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "result", rename_all = "lowercase")]
 pub enum DiffResult {
     Equal,
-    Diff(String),
+    Diff(DiffReport),
+}
+
+/// The parts of a response which are compared, with the skip and mask rules applied.
+struct ResponseParts {
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+    timing: Timing,
 }
 
 impl ResponseContext {
     pub fn new(skip_headers: Vec<String>) -> Self {
-        Self { skip_headers }
+        Self {
+            skip_headers,
+            ..Default::default()
+        }
+    }
+
+    /// Check that all the JSONPaths and regexes in the rules are valid.
+    fn validate(&self) -> Result<()> {
+        self.skip_body_paths()?;
+        self.json_rules()?;
+        self.masks()?;
+        Ok(())
     }
-}
 
-struct Line(Option<usize>);
+    fn skip_body_paths(&self) -> Result<Vec<JsonPath>> {
+        self.skip_body.iter().map(|p| p.parse()).collect()
+    }
+
+    fn masks(&self) -> Result<Vec<(Regex, &str)>> {
+        self.mask
+            .iter()
+            .map(|m| {
+                let re = Regex::new(&m.pattern)
+                    .map_err(|e| anyhow::anyhow!("invalid mask pattern {:?}: {}", m.pattern, e))?;
+                Ok((re, m.replacement.as_str()))
+            })
+            .collect()
+    }
 
-impl fmt::Display for Line {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.0 {
-            None => write!(f, "    "),
-            Some(idx) => write!(f, "{:<4}", idx + 1),
+    /// Apply all the mask rules to the text.
+    fn apply_masks(&self, text: String) -> Result<String> {
+        self.masks()?.iter().try_fold(text, |text, (re, rep)| {
+            Ok(re.replace_all(&text, *rep).into_owned())
+        })
+    }
+
+    /// Apply all the mask rules to the string and number values of the JSON, so that it stays
+    /// valid JSON. A number which doesn't parse as one after masking becomes a string.
+    fn mask_json(&self, json: &mut Value) -> Result<()> {
+        match json {
+            Value::String(s) => *s = self.apply_masks(std::mem::take(s))?,
+            Value::Number(n) => {
+                let text = n.to_string();
+                let masked = self.apply_masks(text.clone())?;
+                if masked != text {
+                    *json = serde_json::from_str::<serde_json::Number>(&masked)
+                        .map_or(Value::String(masked), Value::Number);
+                }
+            }
+            Value::Array(items) => {
+                for v in items {
+                    self.mask_json(v)?;
+                }
+            }
+            Value::Object(map) => {
+                for v in map.values_mut() {
+                    self.mask_json(v)?;
+                }
+            }
+            Value::Null | Value::Bool(_) => {}
         }
+        Ok(())
+    }
+
+    fn json_rules(&self) -> Result<json::Rules> {
+        let unordered_arrays = self
+            .unordered_arrays
+            .iter()
+            .map(|r| Ok((r.path.parse()?, r.key.clone())))
+            .collect::<Result<_>>()?;
+        let compare = self
+            .compare
+            .iter()
+            .map(|r| Ok((r.path.parse()?, r.clone())))
+            .collect::<Result<_>>()?;
+        Ok(json::Rules {
+            unordered_arrays,
+            compare,
+        })
+    }
+}
+
+impl CompareRule {
+    fn equals(&self, v1: &Value, v2: &Value) -> bool {
+        match (v1, v2) {
+            (Value::Number(n1), Value::Number(n2)) => match (n1.as_f64(), n2.as_f64()) {
+                (Some(n1), Some(n2)) => self.numbers_equal(n1, n2),
+                _ => false,
+            },
+            (Value::String(s1), Value::String(s2)) if self.ignore_case => {
+                s1.to_lowercase() == s2.to_lowercase()
+            }
+            (Value::String(s), v) | (v, Value::String(s)) if self.coerce_types => match v {
+                Value::Number(n) => match (s.trim().parse::<f64>(), n.as_f64()) {
+                    (Ok(n1), Some(n2)) => self.numbers_equal(n1, n2),
+                    _ => false,
+                },
+                Value::Bool(b) => s.trim() == b.to_string(),
+                Value::Null => s == "null",
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn numbers_equal(&self, n1: f64, n2: f64) -> bool {
+        let delta = (n1 - n2).abs();
+        delta == 0.0
+            || self.tolerance.is_some_and(|t| delta <= t)
+            || self
+                .relative_tolerance
+                .is_some_and(|t| delta <= t * n1.abs().max(n2.abs()))
     }
 }
 
@@ -65,24 +271,42 @@ impl DiffConfig {
         let ctx = DiffContext::new(req1, req2, res);
         let mut ctxs = HashMap::new();
         ctxs.insert(profile, ctx);
-        Self { ctxs }
+        Self {
+            ctxs,
+            invalid: HashMap::new(),
+        }
     }
 
     pub async fn try_load(path: impl AsRef<Path>) -> Result<DiffConfig> {
-        let file = fs::read_to_string(path).await?;
-        let config: DiffConfig = serde_yaml::from_str(&file)?;
-        for (profile, ctx) in config.ctxs.iter() {
+        Self::try_load_with(path, &LoadOptions::default()).await
+    }
+
+    pub async fn try_load_with(path: impl AsRef<Path>, opts: &LoadOptions) -> Result<DiffConfig> {
+        let (value, invalid) = config::load_yaml(path.as_ref(), opts, ConfigKind::Diff).await?;
+        let mut config: DiffConfig = serde_yaml::from_value(value)?;
+        config.invalid = invalid;
+        for (profile, ctx) in config.ctxs.iter_mut() {
             if !ctx.request1.params.is_object() || !ctx.request2.params.is_object() {
                 return Err(anyhow::anyhow!(
                     "params in request1 or request2 must be an object in profile: {}",
                     profile
                 ));
             }
+            ctx.request1
+                .validate()
+                .and_then(|_| ctx.request2.validate())
+                .and_then(|_| ctx.response.validate())
+                .map_err(|e| anyhow::anyhow!("{} in profile: {}", e, profile))?;
+            ctx.request1.resolve_paths(path.as_ref());
+            ctx.request2.resolve_paths(path.as_ref());
         }
         Ok(config)
     }
 
     pub fn get(&self, profile: &str) -> Result<&DiffContext> {
+        if let Some(invalid) = self.invalid.get(profile) {
+            return Err(anyhow::anyhow!("{}", invalid.error));
+        }
         self.ctxs.get(profile).ok_or_else(|| {
             anyhow::anyhow!(
                 "profile {} not found. Available profiles: {:?}.",
@@ -92,6 +316,22 @@ impl DiffConfig {
         })
     }
 
+    /// Iterate over all the profiles, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &DiffContext)> {
+        self.ctxs.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Iterate over the names and tags of the profiles which couldn't be expanded, e.g. a
+    /// `request` template without a `left` environment, in no particular order. [`get`] returns
+    /// their error.
+    ///
+    /// [`get`]: Self::get
+    pub fn iter_invalid(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.invalid
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.tags.as_slice()))
+    }
+
     pub async fn diff(&self, profile: &str) -> Result<DiffResult> {
         let ctx = self.get(profile)?;
 
@@ -105,101 +345,345 @@ impl DiffContext {
             request1: req1,
             request2: req2,
             response: resp,
+            tags: Vec::new(),
         }
     }
 
     pub async fn diff(&self) -> Result<DiffResult> {
-        let res1 = self.request1.send().await?;
-        let res2 = self.request2.send().await?;
+        // send both at the same time so that the data has less chance to change in between
+        let (parts1, parts2) =
+            tokio::try_join!(self.send(&self.request1), self.send(&self.request2))?;
 
-        self.diff_response(res1, res2).await
+        self.diff_parts(parts1, parts2)
     }
 
-    async fn diff_response(&self, res1: Response, res2: Response) -> Result<DiffResult> {
-        let url1 = res1.url().to_string();
-        let url2 = res2.url().to_string();
+    /// Send request1 and record its normalized response.
+    pub async fn snapshot(&self) -> Result<Snapshot> {
+        let parts = self.send(&self.request1).await?;
+        self.to_snapshot(parts)
+    }
 
-        let text1 = self.request_to_string(res1).await?;
-        let text2 = self.request_to_string(res2).await?;
+    /// Send request1 and diff the live response against the snapshot, with the snapshot as the
+    /// old side.
+    pub async fn check(&self, snapshot: &Snapshot) -> Result<DiffResult> {
+        let parts = self.send(&self.request1).await?;
+        self.diff_parts(snapshot.to_parts(), parts)
+    }
 
-        if text1 != text2 {
-            let headers = format!("--- a/{}\n+++ b/{}\n", url1, url2);
-            return Ok(DiffResult::Diff(build_diff(headers, text1, text2)?));
-        }
+    fn to_snapshot(&self, parts: ResponseParts) -> Result<Snapshot> {
+        let json = self.parse_body(&parts.body)?;
+        let body = pretty_body(parts.body.clone(), json)?;
+        Ok(Snapshot::new(parts, body))
+    }
 
-        Ok(DiffResult::Equal)
+    async fn send(&self, req: &RequestContext) -> Result<ResponseParts> {
+        let start = Instant::now();
+        let res = req.send().await?;
+        // with retries, only the attempt which got the response is timed
+        let start = res
+            .extensions()
+            .get::<AttemptStart>()
+            .map_or(start, |attempt| attempt.0);
+        let ttfb = start.elapsed();
+        let mut parts = self.response_to_parts(res).await?;
+        parts.timing = Timing {
+            ttfb_ms: ttfb.as_secs_f64() * 1000.0,
+            total_ms: start.elapsed().as_secs_f64() * 1000.0,
+        };
+        Ok(parts)
     }
 
-    async fn request_to_string(&self, res: Response) -> Result<String> {
-        let mut buf = Vec::new();
+    fn diff_parts(&self, parts1: ResponseParts, parts2: ResponseParts) -> Result<DiffResult> {
+        let status = (parts1.status != parts2.status).then_some(StatusChange {
+            old: parts1.status,
+            new: parts2.status,
+        });
+        let headers = report::diff_headers(&parts1.headers, &parts2.headers);
+
+        let mut json1 = self.parse_body(&parts1.body)?;
+        let mut json2 = self.parse_body(&parts2.body)?;
 
-        writeln!(&mut buf, "{}", res.status()).unwrap();
-        res.headers().iter().for_each(|(k, v)| {
-            if self.response.skip_headers.iter().any(|v| v == k.as_str()) {
-                return;
+        let changes = match (&mut json1, &mut json2) {
+            (Some(v1), Some(v2)) => Some(json::diff_json(v1, v2, &self.response.json_rules()?)),
+            _ => None,
+        };
+
+        let body = match (self.response.diff_mode, changes) {
+            (DiffMode::Json, Some(changes)) if changes.is_empty() => BodyDiff::Equal,
+            (DiffMode::Json, Some(changes)) => BodyDiff::Json { changes },
+            _ => {
+                let text1 = pretty_body(parts1.body, json1)?;
+                let text2 = pretty_body(parts2.body, json2)?;
+                if text1 == text2 {
+                    BodyDiff::Equal
+                } else {
+                    BodyDiff::Text {
+                        hunks: report::diff_text(&text1, &text2),
+                    }
+                }
             }
-            writeln!(&mut buf, "{}: {:?}", k, v).unwrap();
-        });
-        writeln!(&mut buf).unwrap();
+        };
 
-        let mut body = res.text().await?;
+        let latency_regression = self.response.latency_threshold.and_then(|threshold_pct| {
+            let change_pct = report::latency_change_pct(parts1.timing, parts2.timing);
+            (change_pct > threshold_pct).then_some(LatencyRegression {
+                threshold_pct,
+                change_pct,
+            })
+        });
 
-        if let Ok(json) = serde_json::from_str::<Value>(&body) {
-            body = serde_json::to_string_pretty(&json)?;
+        if status.is_none()
+            && headers.is_empty()
+            && body == BodyDiff::Equal
+            && latency_regression.is_none()
+        {
+            return Ok(DiffResult::Equal);
         }
 
-        writeln!(&mut buf, "{}", body).unwrap();
-
-        Ok(String::from_utf8(buf)?)
+        Ok(DiffResult::Diff(DiffReport {
+            url1: parts1.url,
+            url2: parts2.url,
+            timing1: parts1.timing,
+            timing2: parts2.timing,
+            latency_regression,
+            status,
+            headers,
+            body,
+        }))
     }
-}
 
-fn build_diff(headers: String, old: String, new: String) -> Result<String> {
-    let diff = TextDiff::from_lines(&old, &new);
-    let mut buf = Vec::with_capacity(4096);
-    writeln!(&mut buf, "{}", headers).unwrap();
-    for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
-        if idx > 0 {
-            writeln!(&mut buf, "{:-^1$}", "-", 80)?;
-        }
-        for op in group {
-            for change in diff.iter_inline_changes(op) {
-                let (sign, s) = match change.tag() {
-                    ChangeTag::Delete => ("-", Style::new().red()),
-                    ChangeTag::Insert => ("+", Style::new().green()),
-                    ChangeTag::Equal => (" ", Style::new().dim()),
-                };
-                write!(
-                    &mut buf,
-                    "{}{} |{}",
-                    style(Line(change.old_index())).dim(),
-                    style(Line(change.new_index())).dim(),
-                    s.apply_to(sign).bold(),
-                )?;
-                for (emphasized, value) in change.iter_strings_lossy() {
-                    if emphasized {
-                        write!(&mut buf, "{}", s.apply_to(value).underlined().on_black())?;
-                    } else {
-                        write!(&mut buf, "{}", s.apply_to(value))?;
-                    }
-                }
-                if change.missing_newline() {
-                    writeln!(&mut buf)?;
-                }
+    /// Read the response (with the mask rules applied to header values and body) and drop the
+    /// headers in `skip_headers`. A JSON body is masked value by value, so it still parses.
+    async fn response_to_parts(&self, res: Response) -> Result<ResponseParts> {
+        let url = res.url().to_string();
+        let status = res.status().as_u16();
+        let headers = res
+            .headers()
+            .iter()
+            .filter(|(k, _)| !self.response.skip_headers.iter().any(|v| v == k.as_str()))
+            .map(|(k, v)| {
+                let v = String::from_utf8_lossy(v.as_bytes()).into_owned();
+                Ok((k.to_string(), self.response.apply_masks(v)?))
+            })
+            .collect::<Result<_>>()?;
+        let text = res.text().await?;
+        let body = match serde_json::from_str::<Value>(&text) {
+            Ok(mut json) => {
+                self.response.mask_json(&mut json)?;
+                serde_json::to_string(&json)?
             }
+            Err(_) => self.response.apply_masks(text)?,
+        };
+
+        Ok(ResponseParts {
+            url,
+            status,
+            headers,
+            body,
+            timing: Timing::default(),
+        })
+    }
+
+    /// Parse the body as JSON (if it is) and remove the fields in `skip_body`.
+    fn parse_body(&self, body: &str) -> Result<Option<Value>> {
+        let mut json = match serde_json::from_str::<Value>(body) {
+            Ok(json) => json,
+            Err(_) => return Ok(None),
+        };
+        for path in self.response.skip_body_paths()? {
+            path.remove(&mut json);
         }
+        Ok(Some(json))
+    }
+}
+
+fn pretty_body(body: String, json: Option<Value>) -> Result<String> {
+    match json {
+        Some(json) => Ok(serde_json::to_string_pretty(&json)?),
+        None => Ok(body),
     }
-    Ok(String::from_utf8(buf)?)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        test_server::{serve, Reply},
+        Cassette, CassetteMode,
+    };
 
     #[tokio::test]
     async fn diff_request_should_work() {
         let config = DiffConfig::try_load("fixtures/diff.yml").await.unwrap();
-        let result = config.diff("rust").await.unwrap();
+        let mut ctx = config.get("rust").unwrap().clone();
+        // replay the recorded responses, so the test doesn't need network
+        let cassette = Cassette::new("fixtures/cassettes/rust.yml", CassetteMode::Replay);
+        ctx.request1.client.cassette = Some(cassette.clone());
+        ctx.request2.client.cassette = Some(cassette);
+        let result = ctx.diff().await.unwrap();
+        assert_eq!(result, DiffResult::Equal);
+    }
+
+    #[test]
+    fn mask_rules_should_replace_matches() {
+        let res = ResponseContext {
+            mask: vec![
+                MaskRule {
+                    pattern: r"[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}".into(),
+                    replacement: "<uuid>".into(),
+                },
+                MaskRule {
+                    pattern: r"prod-api-\d+(\.internal)".into(),
+                    replacement: "prod-api-N$1".into(),
+                },
+            ],
+            ..Default::default()
+        };
+        let text = "id: 5f2b6e0a-1c3d-4e5f-8a9b-0c1d2e3f4a5b\nhost: prod-api-12.internal\n";
+        assert_eq!(
+            res.apply_masks(text.into()).unwrap(),
+            "id: <uuid>\nhost: prod-api-N.internal\n"
+        );
+
+        let res = ResponseContext {
+            mask: vec![MaskRule {
+                pattern: "(".into(),
+                replacement: default_mask_replacement(),
+            }],
+            ..Default::default()
+        };
+        assert!(res.validate().is_err());
+    }
+
+    #[test]
+    fn mask_rules_should_keep_json_valid() {
+        let res = ResponseContext {
+            mask: vec![MaskRule {
+                pattern: r"\d{13}".into(),
+                replacement: default_mask_replacement(),
+            }],
+            ..Default::default()
+        };
+        let mut json =
+            serde_json::json!({"ts": 1700000000000u64, "id": 1, "at": ["x 1700000000000"]});
+        res.mask_json(&mut json).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"ts": "<masked>", "id": 1, "at": ["x <masked>"]})
+        );
+    }
+
+    #[tokio::test]
+    async fn timing_should_exclude_retries() {
+        let addr = serve(|req| match req.n {
+            1 => Reply::status(503),
+            _ => Reply::ok(""),
+        })
+        .await;
+        let url = format!("http://{}/", addr);
+        let mut req: RequestContext = url.parse().unwrap();
+        req.client = serde_yaml::from_str("retries: 1\nretry_backoff: 1").unwrap();
+        let ctx = DiffContext::new(req, url.parse().unwrap(), ResponseContext::default());
+
+        let parts = ctx.send(&ctx.request1).await.unwrap();
+        assert_eq!(parts.status, 200);
+        assert!(parts.timing.total_ms < 1000.0);
+    }
+
+    #[tokio::test]
+    async fn check_should_diff_against_snapshot() {
+        let parts = |body: &str| ResponseParts {
+            url: "http://localhost/todos/1".into(),
+            status: 200,
+            headers: vec![("content-type".into(), "application/json".into())],
+            body: body.into(),
+            timing: Timing::default(),
+        };
+        let mut ctx = DiffContext::new(
+            "http://localhost/todos/1".parse().unwrap(),
+            "http://localhost/todos/1".parse().unwrap(),
+            ResponseContext::default(),
+        );
+        ctx.response.skip_body = vec!["$.updated_at".into()];
+
+        let res = parts(r#"{"id":1,"title":"a","updated_at":"2024-01-01"}"#);
+        let snapshot = ctx.to_snapshot(res).unwrap();
+        assert_eq!(snapshot.body, "{\n  \"id\": 1,\n  \"title\": \"a\"\n}");
+
+        let path = std::env::temp_dir().join(format!("xreq-snapshot-{}.yml", std::process::id()));
+        snapshot.save(&path).await.unwrap();
+        let snapshot = Snapshot::load(&path).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let res = parts(r#"{"updated_at":"2024-02-01","title":"a","id":1}"#);
+        let result = ctx.diff_parts(snapshot.to_parts(), res).unwrap();
+        assert_eq!(result, DiffResult::Equal);
+
+        let res = parts(r#"{"id":1,"title":"b"}"#);
+        match ctx.diff_parts(snapshot.to_parts(), res).unwrap() {
+            DiffResult::Diff(report) => assert!(matches!(report.body, BodyDiff::Text { .. })),
+            DiffResult::Equal => panic!("expected a body diff"),
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_should_only_need_the_left_environment() {
+        let addr = serve(|_| Reply::json(r#"{"id":1}"#)).await;
+        let path = std::env::temp_dir().join(format!("xdiff-left-{}.yml", std::process::id()));
+        let config = format!(
+            "environments:\n  local:\n    base_url: http://{}\n\
+             todo:\n  left: local\n  request:\n    url: /todos/1\n",
+            addr
+        );
+        std::fs::write(&path, config).unwrap();
+
+        let opts = LoadOptions {
+            left_only: true,
+            ..Default::default()
+        };
+        let config = DiffConfig::try_load_with(&path, &opts).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        let snapshot = config.get("todo").unwrap().snapshot().await.unwrap();
+        assert_eq!(snapshot.url, format!("http://{}/todos/1", addr));
+        assert_eq!(snapshot.body, "{\n  \"id\": 1\n}");
+    }
+
+    #[test]
+    fn diff_parts_should_flag_latency_regression() {
+        let parts = |total_ms: f64| ResponseParts {
+            url: "http://localhost/".into(),
+            status: 200,
+            headers: vec![("content-type".into(), "application/json".into())],
+            body: r#"{"a": 1}"#.into(),
+            timing: Timing {
+                ttfb_ms: total_ms / 2.0,
+                total_ms,
+            },
+        };
+        let mut ctx = DiffContext::new(
+            "http://localhost/".parse().unwrap(),
+            "http://localhost/".parse().unwrap(),
+            ResponseContext::default(),
+        );
+        let result = ctx.diff_parts(parts(100.0), parts(150.0)).unwrap();
+        assert_eq!(result, DiffResult::Equal);
+
+        ctx.response.latency_threshold = Some(20.0);
+        let result = ctx.diff_parts(parts(100.0), parts(150.0)).unwrap();
+        match result {
+            DiffResult::Diff(report) => {
+                assert_eq!(report.body, BodyDiff::Equal);
+                assert_eq!(
+                    report.latency_regression,
+                    Some(LatencyRegression {
+                        threshold_pct: 20.0,
+                        change_pct: 50.0
+                    })
+                );
+            }
+            DiffResult::Equal => panic!("expected a latency regression"),
+        }
+        let result = ctx.diff_parts(parts(100.0), parts(110.0)).unwrap();
         assert_eq!(result, DiffResult::Equal);
     }
 }
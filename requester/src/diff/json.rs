@@ -0,0 +1,501 @@
+use super::CompareRule;
+use crate::jsonpath::{format_location, JsonPath, PathElem};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// A single structural change between two JSON documents, addressed by its JSON path.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum JsonChange {
+    Added {
+        path: String,
+        value: Value,
+    },
+    Removed {
+        path: String,
+        value: Value,
+    },
+    Changed {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl fmt::Display for JsonChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonChange::Added { path, value } => write!(f, "{}: {}", path, value),
+            JsonChange::Removed { path, value } => write!(f, "{}: {}", path, value),
+            JsonChange::Changed { path, old, new } => write!(f, "{}: {} -> {}", path, old, new),
+        }
+    }
+}
+
+/// Rules compiled from the `ResponseContext` which change how parts of the bodies are compared.
+#[derive(Debug, Default)]
+pub(crate) struct Rules {
+    /// arrays compared regardless of order, optionally matching elements by an identity key
+    pub unordered_arrays: Vec<(JsonPath, Option<String>)>,
+    /// relaxed equality for the values at the matching paths
+    pub compare: Vec<(JsonPath, CompareRule)>,
+}
+
+impl Rules {
+    /// Merge all the compare rules matching the location, `None` if there's no such rule.
+    fn compare_rule(&self, location: &[PathElem]) -> Option<CompareRule> {
+        self.compare
+            .iter()
+            .filter(|(path, _)| path.matches(location))
+            .map(|(_, rule)| rule)
+            .fold(None, |acc: Option<CompareRule>, rule| {
+                let max = |a: Option<f64>, b: Option<f64>| match (a, b) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                };
+                Some(match acc {
+                    None => rule.clone(),
+                    Some(acc) => CompareRule {
+                        path: acc.path,
+                        tolerance: max(acc.tolerance, rule.tolerance),
+                        relative_tolerance: max(acc.relative_tolerance, rule.relative_tolerance),
+                        coerce_types: acc.coerce_types || rule.coerce_types,
+                        ignore_case: acc.ignore_case || rule.ignore_case,
+                        null_as_missing: acc.null_as_missing || rule.null_as_missing,
+                    },
+                })
+            })
+    }
+
+    fn null_as_missing(&self, location: &mut Vec<PathElem>, key: &str, value: &Value) -> bool {
+        if !value.is_null() {
+            return false;
+        }
+        location.push(PathElem::Key(key.to_string()));
+        let result = self
+            .compare_rule(location)
+            .is_some_and(|rule| rule.null_as_missing);
+        location.pop();
+        result
+    }
+}
+
+/// Compare two JSON values and return the changes ordered by their position in the documents.
+///
+/// Unordered arrays are sorted in place, so that a line based diff of the values afterwards
+/// only shows the genuine changes.
+pub(crate) fn diff_json(old: &mut Value, new: &mut Value, rules: &Rules) -> Vec<JsonChange> {
+    let mut changes = Vec::new();
+    walk(&mut Vec::new(), old, new, rules, &mut changes);
+    changes
+}
+
+fn walk(
+    location: &mut Vec<PathElem>,
+    old: &mut Value,
+    new: &mut Value,
+    rules: &Rules,
+    changes: &mut Vec<JsonChange>,
+) {
+    match (old, new) {
+        (Value::Object(o1), Value::Object(o2)) => {
+            // a `null` on one side and a missing key on the other may be equivalent
+            let missing1: Vec<String> = o2
+                .iter()
+                .filter(|(k, v)| !o1.contains_key(*k) && rules.null_as_missing(location, k, v))
+                .map(|(k, _)| k.clone())
+                .collect();
+            let missing2: Vec<String> = o1
+                .iter()
+                .filter(|(k, v)| !o2.contains_key(*k) && rules.null_as_missing(location, k, v))
+                .map(|(k, _)| k.clone())
+                .collect();
+            o1.extend(missing1.into_iter().map(|k| (k, Value::Null)));
+            o2.extend(missing2.into_iter().map(|k| (k, Value::Null)));
+
+            for (k, v1) in o1.iter_mut() {
+                location.push(PathElem::Key(k.clone()));
+                match o2.get_mut(k) {
+                    Some(v2) => walk(location, v1, v2, rules, changes),
+                    None => removed(location, v1, changes),
+                }
+                location.pop();
+            }
+            for (k, v2) in o2.iter() {
+                if !o1.contains_key(k) {
+                    location.push(PathElem::Key(k.clone()));
+                    added(location, v2, changes);
+                    location.pop();
+                }
+            }
+        }
+        (Value::Array(a1), Value::Array(a2)) => {
+            let rule = rules
+                .unordered_arrays
+                .iter()
+                .find(|(path, _)| path.matches(location));
+            match rule {
+                Some((_, Some(key))) => walk_keyed(location, a1, a2, key, rules, changes),
+                Some((_, None)) => walk_unordered(location, a1, a2, rules, changes),
+                None => walk_ordered(location, a1, a2, rules, changes),
+            }
+        }
+        (old, new) => {
+            if old == new {
+                return;
+            }
+            if let Some(rule) = rules.compare_rule(location) {
+                if rule.equals(old, new) {
+                    // align both sides so that the line based diff doesn't show it either
+                    *new = old.clone();
+                    return;
+                }
+            }
+            changes.push(JsonChange::Changed {
+                path: format_location(location),
+                old: old.clone(),
+                new: new.clone(),
+            });
+        }
+    }
+}
+
+fn walk_ordered(
+    location: &mut Vec<PathElem>,
+    a1: &mut [Value],
+    a2: &mut [Value],
+    rules: &Rules,
+    changes: &mut Vec<JsonChange>,
+) {
+    for i in 0..a1.len().max(a2.len()) {
+        location.push(PathElem::Index(i));
+        match (a1.get_mut(i), a2.get_mut(i)) {
+            (Some(v1), Some(v2)) => walk(location, v1, v2, rules, changes),
+            (Some(v1), None) => removed(location, v1, changes),
+            (None, Some(v2)) => added(location, v2, changes),
+            (None, None) => unreachable!(),
+        }
+        location.pop();
+    }
+}
+
+/// Compare arrays as multisets: an element is either present on both sides or added/removed.
+/// Elements are equal if they're identical or equal under the compare rules. The added and
+/// removed elements are located by their index in the new and old array respectively.
+fn walk_unordered(
+    location: &mut Vec<PathElem>,
+    a1: &mut [Value],
+    a2: &mut [Value],
+    rules: &Rules,
+    changes: &mut Vec<JsonChange>,
+) {
+    let mut matched1 = vec![false; a1.len()];
+    let mut matched2 = vec![false; a2.len()];
+    // identical elements first, so that a relaxed match doesn't take the pair of an identical one
+    let canonical2: Vec<String> = a2.iter().map(canonical).collect();
+    for (i, v1) in a1.iter().enumerate() {
+        let c = canonical(v1);
+        if let Some(j) = (0..a2.len()).find(|&j| !matched2[j] && canonical2[j] == c) {
+            matched1[i] = true;
+            matched2[j] = true;
+        }
+    }
+
+    for i in 0..a1.len() {
+        if matched1[i] {
+            continue;
+        }
+        location.push(PathElem::OldIndex(i));
+        for j in (0..a2.len()).filter(|&j| !matched2[j]) {
+            // the walk aligns the values which are equal under the rules
+            let (mut v1, mut v2) = (a1[i].clone(), a2[j].clone());
+            let mut found = Vec::new();
+            walk(location, &mut v1, &mut v2, rules, &mut found);
+            if found.is_empty() {
+                (a1[i], a2[j]) = (v1, v2);
+                matched1[i] = true;
+                matched2[j] = true;
+                break;
+            }
+        }
+        if !matched1[i] {
+            removed(location, &a1[i], changes);
+        }
+        location.pop();
+    }
+    for j in (0..a2.len()).filter(|&j| !matched2[j]) {
+        location.push(PathElem::NewIndex(j));
+        added(location, &a2[j], changes);
+        location.pop();
+    }
+
+    a1.sort_by_cached_key(canonical);
+    a2.sort_by_cached_key(canonical);
+}
+
+/// Compare arrays by pairing up elements with the same value of the identity key.
+/// Elements without the key are compared as a multiset.
+fn walk_keyed(
+    location: &mut Vec<PathElem>,
+    a1: &mut Vec<Value>,
+    a2: &mut Vec<Value>,
+    key: &str,
+    rules: &Rules,
+    changes: &mut Vec<JsonChange>,
+) {
+    let id = |v: &Value| v.get(key).map(canonical);
+    let (keyed1, mut keyless1): (Vec<Value>, Vec<Value>) = std::mem::take(a1)
+        .into_iter()
+        .partition(|v| id(v).is_some());
+    let (keyed2, mut keyless2): (Vec<Value>, Vec<Value>) = std::mem::take(a2)
+        .into_iter()
+        .partition(|v| id(v).is_some());
+    *a1 = keyed1;
+    *a2 = keyed2;
+
+    let mut matched = vec![false; a2.len()];
+    for v1 in a1.iter_mut() {
+        let k = id(v1);
+        location.push(PathElem::Match {
+            key: key.to_string(),
+            value: v1[key].clone(),
+        });
+        match (0..a2.len()).find(|&j| !matched[j] && id(&a2[j]) == k) {
+            Some(j) => {
+                matched[j] = true;
+                walk(location, v1, &mut a2[j], rules, changes);
+            }
+            None => removed(location, v1, changes),
+        }
+        location.pop();
+    }
+    for (j, v2) in a2.iter().enumerate() {
+        if !matched[j] {
+            location.push(PathElem::Match {
+                key: key.to_string(),
+                value: v2[key].clone(),
+            });
+            added(location, v2, changes);
+            location.pop();
+        }
+    }
+
+    walk_unordered(location, &mut keyless1, &mut keyless2, rules, changes);
+    a1.sort_by_cached_key(|v| id(v));
+    a2.sort_by_cached_key(|v| id(v));
+    a1.append(&mut keyless1);
+    a2.append(&mut keyless2);
+}
+
+fn added(location: &[PathElem], value: &Value, changes: &mut Vec<JsonChange>) {
+    changes.push(JsonChange::Added {
+        path: format_location(location),
+        value: value.clone(),
+    });
+}
+
+fn removed(location: &[PathElem], value: &Value, changes: &mut Vec<JsonChange>) {
+    changes.push(JsonChange::Removed {
+        path: format_location(location),
+        value: value.clone(),
+    });
+}
+
+/// A stable string form of the value used for equality and ordering of array elements.
+fn canonical(v: &Value) -> String {
+    // serde_json objects are ordered by key, so the serialized form is canonical
+    v.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diff_json_should_report_changes_by_path() {
+        let old = json!({"items": [{"id": 1, "price": 10}], "meta": {"a": 1}, "x-y": true});
+        let new = json!({"meta": {"a": 1, "b": 2}, "items": [{"id": 1, "price": 12}, {"id": 2}]});
+        let changes = diff_json(&mut old.clone(), &mut new.clone(), &Rules::default());
+        assert_eq!(
+            changes,
+            vec![
+                JsonChange::Changed {
+                    path: "$.items[0].price".into(),
+                    old: json!(10),
+                    new: json!(12)
+                },
+                JsonChange::Added {
+                    path: "$.items[1]".into(),
+                    value: json!({"id": 2})
+                },
+                JsonChange::Added {
+                    path: "$.meta.b".into(),
+                    value: json!(2)
+                },
+                JsonChange::Removed {
+                    path: "$['x-y']".into(),
+                    value: json!(true)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_json_should_ignore_key_order() {
+        let old = json!({"a": 1, "b": [1, 2]});
+        let new = json!({"b": [1, 2], "a": 1});
+        assert!(diff_json(&mut old.clone(), &mut new.clone(), &Rules::default()).is_empty());
+    }
+
+    #[test]
+    fn diff_json_should_support_unordered_arrays() {
+        let rules = Rules {
+            unordered_arrays: vec![
+                ("$.tags".parse().unwrap(), None),
+                ("$.items".parse().unwrap(), Some("id".to_string())),
+            ],
+            ..Default::default()
+        };
+        let mut old = json!({
+            "tags": ["a", "b", "c"],
+            "items": [{"id": 1, "price": 10}, {"id": 2, "price": 20}, {"id": 3}]
+        });
+        let mut new = json!({
+            "tags": ["c", "d", "a"],
+            "items": [{"id": 3}, {"id": 4}, {"id": 1, "price": 12}]
+        });
+        let changes = diff_json(&mut old, &mut new, &rules);
+        assert_eq!(
+            changes,
+            vec![
+                JsonChange::Changed {
+                    path: "$.items[?(@.id==1)].price".into(),
+                    old: json!(10),
+                    new: json!(12)
+                },
+                JsonChange::Removed {
+                    path: "$.items[?(@.id==2)]".into(),
+                    value: json!({"id": 2, "price": 20})
+                },
+                JsonChange::Added {
+                    path: "$.items[?(@.id==4)]".into(),
+                    value: json!({"id": 4})
+                },
+                JsonChange::Removed {
+                    path: "$.tags[old:1]".into(),
+                    value: json!("b")
+                },
+                JsonChange::Added {
+                    path: "$.tags[new:1]".into(),
+                    value: json!("d")
+                },
+            ]
+        );
+        // arrays are aligned for the line based diff
+        assert_eq!(old["tags"], json!(["a", "b", "c"]));
+        assert_eq!(new["tags"], json!(["a", "c", "d"]));
+        assert_eq!(new["items"][0]["id"], json!(1));
+    }
+
+    #[test]
+    fn unordered_arrays_should_apply_compare_rules() {
+        let rules = Rules {
+            unordered_arrays: vec![
+                ("$.prices".parse().unwrap(), None),
+                ("$.tags".parse().unwrap(), None),
+            ],
+            compare: [
+                CompareRule {
+                    path: "$.prices[*]".into(),
+                    tolerance: Some(0.01),
+                    ..Default::default()
+                },
+                CompareRule {
+                    path: "$.tags[*]".into(),
+                    ignore_case: true,
+                    ..Default::default()
+                },
+            ]
+            .into_iter()
+            .map(|r| (r.path.parse().unwrap(), r))
+            .collect(),
+        };
+        let mut old = json!({"prices": [1.0, 2.0, 3.0], "tags": ["a", "B"]});
+        let mut new = json!({"prices": [2.001, 1.0, 4.0], "tags": ["b", "A"]});
+        let changes = diff_json(&mut old, &mut new, &rules);
+        assert_eq!(
+            changes,
+            vec![
+                JsonChange::Removed {
+                    path: "$.prices[old:2]".into(),
+                    value: json!(3.0)
+                },
+                JsonChange::Added {
+                    path: "$.prices[new:2]".into(),
+                    value: json!(4.0)
+                },
+            ]
+        );
+        // the relaxed matches are aligned for the line based diff
+        assert_eq!(new["prices"], json!([1.0, 2.0, 4.0]));
+        assert_eq!(old["tags"], new["tags"]);
+    }
+
+    #[test]
+    fn diff_json_should_apply_compare_rules() {
+        let rule = |path: &str| CompareRule {
+            path: path.to_string(),
+            ..Default::default()
+        };
+        let rules = Rules {
+            compare: [
+                CompareRule {
+                    tolerance: Some(0.01),
+                    ..rule("$..price")
+                },
+                CompareRule {
+                    relative_tolerance: Some(0.1),
+                    ..rule("$.score")
+                },
+                CompareRule {
+                    coerce_types: true,
+                    ..rule("$.count")
+                },
+                CompareRule {
+                    ignore_case: true,
+                    ..rule("$.name")
+                },
+                CompareRule {
+                    null_as_missing: true,
+                    ..rule("$.*")
+                },
+            ]
+            .into_iter()
+            .map(|r| (r.path.parse().unwrap(), r))
+            .collect(),
+            ..Default::default()
+        };
+        let mut old = json!({
+            "items": [{"price": 9.999}], "score": 100, "count": "3", "name": "Foo",
+            "deleted": null, "other": 1.0
+        });
+        let mut new = json!({
+            "items": [{"price": 10.0}], "score": 95, "count": 3, "name": "FOO",
+            "extra": null, "other": 1.001
+        });
+        let changes = diff_json(&mut old, &mut new, &rules);
+        assert_eq!(
+            changes,
+            vec![JsonChange::Changed {
+                path: "$.other".into(),
+                old: json!(1.0),
+                new: json!(1.001)
+            }]
+        );
+        assert_eq!(old["extra"], Value::Null);
+        assert_eq!(new["deleted"], Value::Null);
+        assert_eq!(new["count"], json!("3"));
+    }
+}
@@ -0,0 +1,411 @@
+use super::json::JsonChange;
+use console::{style, Style};
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use std::{collections::BTreeMap, fmt, ops::Range};
+
+/// What differs between the two responses.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffReport {
+    pub url1: String,
+    pub url2: String,
+    pub timing1: Timing,
+    pub timing2: Timing,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_regression: Option<LatencyRegression>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<StatusChange>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<HeaderChange>,
+    pub body: BodyDiff,
+}
+
+/// Timing of a request. DNS lookup and connect times aren't exposed by the HTTP client, so they
+/// are included in the time to first byte.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Timing {
+    /// time from sending the request until the response headers are received
+    pub ttfb_ms: f64,
+    /// time from sending the request until the whole body is received
+    pub total_ms: f64,
+}
+
+/// Request2 is slower than request1 by more than the configured threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencyRegression {
+    pub threshold_pct: f64,
+    pub change_pct: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StatusChange {
+    pub old: u16,
+    pub new: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum HeaderChange {
+    Added {
+        name: String,
+        value: String,
+    },
+    Removed {
+        name: String,
+        value: String,
+    },
+    Changed {
+        name: String,
+        old: String,
+        new: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BodyDiff {
+    Equal,
+    /// line based diff of the (pretty-printed) bodies
+    Text {
+        hunks: Vec<Hunk>,
+    },
+    /// structural diff of JSON bodies
+    Json {
+        changes: Vec<JsonChange>,
+    },
+}
+
+/// A group of changed lines with up to 3 lines of context around them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Hunk {
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffLine {
+    pub tag: LineTag,
+    /// 1-based line number in the first body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_line: Option<usize>,
+    /// 1-based line number in the second body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_line: Option<usize>,
+    pub text: String,
+    /// byte ranges of `text` which changed within the line, only used for rendering
+    #[serde(skip)]
+    pub emphasis: Vec<Range<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineTag {
+    Equal,
+    Delete,
+    Insert,
+}
+
+struct Line(Option<usize>);
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            None => write!(f, "    "),
+            Some(idx) => write!(f, "{:<4}", idx),
+        }
+    }
+}
+
+/// Compare the headers by name. Repeated headers are compared by their joined values.
+pub(crate) fn diff_headers(
+    headers1: &[(String, String)],
+    headers2: &[(String, String)],
+) -> Vec<HeaderChange> {
+    let (h1, h2) = (group_headers(headers1), group_headers(headers2));
+
+    let mut changes = Vec::new();
+    for (name, v1) in &h1 {
+        match h2.get(name) {
+            Some(v2) if v1 == v2 => {}
+            Some(v2) => changes.push(HeaderChange::Changed {
+                name: name.clone(),
+                old: v1.clone(),
+                new: v2.clone(),
+            }),
+            None => changes.push(HeaderChange::Removed {
+                name: name.clone(),
+                value: v1.clone(),
+            }),
+        }
+    }
+    for (name, v2) in &h2 {
+        if !h1.contains_key(name) {
+            changes.push(HeaderChange::Added {
+                name: name.clone(),
+                value: v2.clone(),
+            });
+        }
+    }
+    changes
+}
+
+/// Headers by name, with the values of a repeated header joined by `, `.
+pub(crate) fn group_headers(headers: &[(String, String)]) -> BTreeMap<String, String> {
+    let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (k, v) in headers {
+        map.entry(k.clone()).or_default().push(v.clone());
+    }
+    map.into_iter().map(|(k, v)| (k, v.join(", "))).collect()
+}
+
+/// Line based diff of the two texts, grouped in hunks.
+pub(crate) fn diff_text(old: &str, new: &str) -> Vec<Hunk> {
+    let diff = TextDiff::from_lines(old, new);
+    diff.grouped_ops(3)
+        .iter()
+        .map(|group| {
+            let mut lines = Vec::new();
+            for op in group {
+                for change in diff.iter_inline_changes(op) {
+                    let mut text = String::new();
+                    let mut emphasis = Vec::new();
+                    for (emphasized, value) in change.iter_strings_lossy() {
+                        let value = value.trim_end_matches('\n');
+                        if emphasized {
+                            emphasis.push(text.len()..text.len() + value.len());
+                        }
+                        text.push_str(value);
+                    }
+                    lines.push(DiffLine {
+                        tag: match change.tag() {
+                            ChangeTag::Delete => LineTag::Delete,
+                            ChangeTag::Insert => LineTag::Insert,
+                            ChangeTag::Equal => LineTag::Equal,
+                        },
+                        old_line: change.old_index().map(|i| i + 1),
+                        new_line: change.new_index().map(|i| i + 1),
+                        text,
+                        emphasis,
+                    });
+                }
+            }
+            Hunk { lines }
+        })
+        .collect()
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "--- a/{}\n+++ b/{}\n", self.url1, self.url2)?;
+
+        let changed = Style::new().yellow();
+        if let Some(StatusChange { old, new }) = self.status {
+            writeln!(
+                f,
+                "{} {}",
+                changed.apply_to("~").bold(),
+                changed.apply_to(format!("status: {} -> {}", old, new))
+            )?;
+        }
+        for change in &self.headers {
+            let (sign, s, text) = match change {
+                HeaderChange::Added { name, value } => {
+                    ("+", Style::new().green(), format!("{}: {:?}", name, value))
+                }
+                HeaderChange::Removed { name, value } => {
+                    ("-", Style::new().red(), format!("{}: {:?}", name, value))
+                }
+                HeaderChange::Changed { name, old, new } => (
+                    "~",
+                    changed.clone(),
+                    format!("{}: {:?} -> {:?}", name, old, new),
+                ),
+            };
+            writeln!(f, "{} {}", s.apply_to(sign).bold(), s.apply_to(text))?;
+        }
+        if self.status.is_some() || !self.headers.is_empty() {
+            writeln!(f)?;
+        }
+
+        match &self.body {
+            BodyDiff::Equal => {}
+            BodyDiff::Text { hunks } => render_hunks(f, hunks)?,
+            BodyDiff::Json { changes } => render_changes(f, changes)?,
+        }
+
+        // a snapshot has no timing, there's nothing to compare it to
+        let (t1, t2) = (self.timing1, self.timing2);
+        if t1 != Timing::default() && t2 != Timing::default() {
+            writeln!(
+                f,
+                "\n{}",
+                style(format!(
+                    "latency: ttfb {:.1}ms -> {:.1}ms, total {:.1}ms -> {:.1}ms ({:+.1}%)",
+                    t1.ttfb_ms,
+                    t2.ttfb_ms,
+                    t1.total_ms,
+                    t2.total_ms,
+                    latency_change_pct(t1, t2)
+                ))
+                .dim()
+            )?;
+        }
+        if let Some(LatencyRegression {
+            threshold_pct,
+            change_pct,
+        }) = self.latency_regression
+        {
+            let s = Style::new().red();
+            writeln!(
+                f,
+                "{} {}",
+                s.apply_to("!").bold(),
+                s.apply_to(format!(
+                    "latency regression: request2 is {:.1}% slower (threshold {}%)",
+                    change_pct, threshold_pct
+                ))
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// How much slower (positive) or faster (negative) the second request is, in percent of the total
+/// time of the first one.
+pub(crate) fn latency_change_pct(t1: Timing, t2: Timing) -> f64 {
+    if t1.total_ms > 0.0 {
+        (t2.total_ms - t1.total_ms) / t1.total_ms * 100.0
+    } else {
+        0.0
+    }
+}
+
+fn render_hunks(f: &mut fmt::Formatter, hunks: &[Hunk]) -> fmt::Result {
+    for (idx, hunk) in hunks.iter().enumerate() {
+        if idx > 0 {
+            writeln!(f, "{:-^1$}", "-", 80)?;
+        }
+        for line in &hunk.lines {
+            let (sign, s) = match line.tag {
+                LineTag::Delete => ("-", Style::new().red()),
+                LineTag::Insert => ("+", Style::new().green()),
+                LineTag::Equal => (" ", Style::new().dim()),
+            };
+            write!(
+                f,
+                "{}{} |{}",
+                style(Line(line.old_line)).dim(),
+                style(Line(line.new_line)).dim(),
+                s.apply_to(sign).bold(),
+            )?;
+            let mut pos = 0;
+            for range in &line.emphasis {
+                write!(f, "{}", s.apply_to(&line.text[pos..range.start]))?;
+                write!(
+                    f,
+                    "{}",
+                    s.apply_to(&line.text[range.clone()])
+                        .underlined()
+                        .on_black()
+                )?;
+                pos = range.end;
+            }
+            writeln!(f, "{}", s.apply_to(&line.text[pos..]))?;
+        }
+    }
+    Ok(())
+}
+
+/// Render the JSON changes with the same color scheme as the line based diff.
+fn render_changes(f: &mut fmt::Formatter, changes: &[JsonChange]) -> fmt::Result {
+    for change in changes {
+        let (sign, s) = match change {
+            JsonChange::Added { .. } => ("+", Style::new().green()),
+            JsonChange::Removed { .. } => ("-", Style::new().red()),
+            JsonChange::Changed { .. } => ("~", Style::new().yellow()),
+        };
+        writeln!(f, "{} {}", s.apply_to(sign).bold(), s.apply_to(change))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_headers_should_work() {
+        let h = |k: &str, v: &str| (k.to_string(), v.to_string());
+        let changes = diff_headers(
+            &[h("a", "1"), h("b", "2"), h("c", "3"), h("c", "4")],
+            &[h("c", "3"), h("c", "4"), h("b", "5"), h("d", "6")],
+        );
+        assert_eq!(
+            changes,
+            vec![
+                HeaderChange::Removed {
+                    name: "a".into(),
+                    value: "1".into()
+                },
+                HeaderChange::Changed {
+                    name: "b".into(),
+                    old: "2".into(),
+                    new: "5".into()
+                },
+                HeaderChange::Added {
+                    name: "d".into(),
+                    value: "6".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_text_should_group_changes_in_hunks() {
+        let old = (1..=20).map(|i| format!("{}\n", i)).collect::<String>();
+        let new = (1..=20)
+            .map(|i| match i {
+                2 => "two\n".to_string(),
+                18 => "eighteen\n".to_string(),
+                i => format!("{}\n", i),
+            })
+            .collect::<String>();
+        let hunks = diff_text(&old, &new);
+        assert_eq!(hunks.len(), 2);
+        let changed: Vec<_> = hunks[0]
+            .lines
+            .iter()
+            .filter(|l| l.tag != LineTag::Equal)
+            .map(|l| (l.tag, l.old_line, l.new_line, l.text.as_str()))
+            .collect();
+        assert_eq!(
+            changed,
+            vec![
+                (LineTag::Delete, Some(2), None, "2"),
+                (LineTag::Insert, None, Some(2), "two"),
+            ]
+        );
+    }
+
+    #[test]
+    fn display_should_skip_latency_without_timing() {
+        let timing = Timing {
+            ttfb_ms: 10.0,
+            total_ms: 20.0,
+        };
+        let mut report = DiffReport {
+            url1: "http://a/".into(),
+            url2: "http://b/".into(),
+            timing1: timing,
+            timing2: timing,
+            latency_regression: None,
+            status: Some(StatusChange { old: 200, new: 500 }),
+            headers: vec![],
+            body: BodyDiff::Equal,
+        };
+        assert!(console::strip_ansi_codes(&report.to_string())
+            .contains("latency: ttfb 10.0ms -> 10.0ms, total 20.0ms -> 20.0ms (+0.0%)"));
+
+        report.timing1 = Timing::default();
+        assert!(!report.to_string().contains("latency"));
+    }
+}
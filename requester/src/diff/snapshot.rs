@@ -0,0 +1,65 @@
+use super::{report, ResponseParts, Timing};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+/// A recorded response of request1, normalized with the rules of the `ResponseContext`: the
+/// headers in `skip_headers` are dropped, the masks are applied, and a JSON body is
+/// pretty-printed without the fields in `skip_body`. Live responses are diffed against it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub url: String,
+    pub status: u16,
+    /// values of a repeated header are joined by `, `
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+}
+
+impl Snapshot {
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow!("failed to read snapshot {}: {}", path.display(), e))?;
+        serde_yaml::from_str(&content)
+            .map_err(|e| anyhow!("invalid snapshot {}: {}", path.display(), e))
+    }
+
+    /// Write the snapshot as YAML, creating the parent directories if needed.
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(path, serde_yaml::to_string(self)?)
+            .await
+            .map_err(|e| anyhow!("failed to write snapshot {}: {}", path.display(), e))
+    }
+
+    pub(super) fn new(parts: ResponseParts, body: String) -> Self {
+        Self {
+            url: parts.url,
+            status: parts.status,
+            headers: report::group_headers(&parts.headers),
+            body,
+        }
+    }
+
+    /// The snapshot as the left side of a diff. No timing is recorded, so latency regressions
+    /// aren't flagged against a snapshot.
+    pub(super) fn to_parts(&self) -> ResponseParts {
+        ResponseParts {
+            url: self.url.clone(),
+            status: self.status,
+            headers: self
+                .headers
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            body: self.body.clone(),
+            timing: Timing::default(),
+        }
+    }
+}
@@ -0,0 +1,449 @@
+use crate::{
+    auth::{ApiKeyLocation, Auth, Secret},
+    body::{self, BodyType},
+    req::RequestContext,
+    unix,
+};
+use anyhow::{anyhow, Result};
+use http::{header::CONTENT_TYPE, Method};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Command line tools a request can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Curl,
+    Httpie,
+    Wget,
+}
+
+/// The body of the exported request.
+enum Body {
+    Text(String),
+    File(PathBuf),
+    /// decoded by a `base64 -d` piped into the command
+    Base64(String),
+    /// text fields and `{ file, filename, content_type }` file fields
+    Multipart(Vec<(String, Value)>),
+}
+
+/// The request with the url and the credentials as shell words.
+struct Parts<'a> {
+    method: &'a Method,
+    url: String,
+    unix_socket: Option<PathBuf>,
+    /// `name: value` headers, followed by the secret if the value is a credential
+    headers: Vec<(String, Option<&'a Secret>)>,
+    user_agent: Option<&'a str>,
+    basic: Option<(&'a String, Option<&'a Secret>)>,
+    body: Option<Body>,
+}
+
+/// The options of the command, one per line, and the base64 data decoded into its stdin.
+#[derive(Default)]
+struct Command {
+    lines: Vec<String>,
+    stdin: Option<String>,
+}
+
+/// Render the request as a shell command. Credentials given inline are written in the command,
+/// the ones from environment variables and files are expanded by the shell when it runs.
+pub(crate) fn render(ctx: &RequestContext, format: ExportFormat) -> Result<String> {
+    let mut ctx = ctx.clone();
+    if ctx.url.scheme() == "unix" {
+        let (socket, url) = unix::split_url(&ctx.url)?;
+        ctx.unix_socket = Some(socket.to_path_buf());
+        ctx.url = url;
+    }
+    if ctx.unix_socket.is_some() && format != ExportFormat::Curl {
+        return Err(anyhow!("only curl can send requests over unix sockets"));
+    }
+
+    let body_type = ctx.body_type();
+    let body = match (&ctx.body_file, &ctx.body) {
+        (Some(path), _) => {
+            if !ctx.headers.contains_key(CONTENT_TYPE) {
+                if let Some(mime) = mime_guess::from_path(path).first_raw() {
+                    ctx.headers.insert(CONTENT_TYPE, mime.parse()?);
+                }
+            }
+            Some(Body::File(path.clone()))
+        }
+        (None, Some(body)) => {
+            match body_type.content_type() {
+                Some(default) if !ctx.headers.contains_key(CONTENT_TYPE) => {
+                    ctx.headers.insert(CONTENT_TYPE, default.parse()?);
+                }
+                // the boundary is generated by the tool, so it sets the content-type itself
+                None => {
+                    ctx.headers.remove(CONTENT_TYPE);
+                }
+                _ => {}
+            }
+            Some(match body_type {
+                BodyType::Multipart => Body::Multipart(multipart_fields(body)?),
+                BodyType::Base64 => Body::Base64(body::encode_text(body_type, body)?),
+                _ => Body::Text(body::encode_text(body_type, body)?),
+            })
+        }
+        (None, None) => None,
+    };
+
+    let mut headers: Vec<(String, Option<&Secret>)> = ctx
+        .headers
+        .iter()
+        .map(|(k, v)| Ok((format!("{}: {}", k, v.to_str()?), None)))
+        .collect::<Result<_>>()?;
+    let mut basic = None;
+    let mut url = quote(ctx.url_with_params()?.as_str());
+    match &ctx.auth {
+        Some(Auth::Basic { username, password }) => basic = Some((username, password.as_ref())),
+        Some(Auth::Bearer { token }) => {
+            headers.push(("authorization: Bearer ".to_string(), Some(token)))
+        }
+        Some(Auth::ApiKey {
+            name,
+            value,
+            location: ApiKeyLocation::Header,
+        }) => headers.push((format!("{}: ", name), Some(value))),
+        Some(Auth::ApiKey {
+            name,
+            value,
+            location: ApiKeyLocation::Query,
+        }) => {
+            let mut with_key = ctx.url_with_params()?;
+            let query = match with_key.query() {
+                Some(query) => format!("{}&{}=", query, name),
+                None => format!("{}=", name),
+            };
+            with_key.set_query(Some(&query));
+            url = word(with_key.as_str(), Some(value));
+        }
+        Some(_) => return Err(anyhow!("hmac, oauth2 and aws_sigv4 auth can't be exported")),
+        None => {}
+    }
+
+    let parts = Parts {
+        method: &ctx.method,
+        url,
+        unix_socket: ctx.unix_socket.clone(),
+        headers,
+        user_agent: ctx.user_agent.as_deref(),
+        basic,
+        body,
+    };
+    let Command { lines, stdin } = match format {
+        ExportFormat::Curl => curl(parts)?,
+        ExportFormat::Httpie => httpie(parts)?,
+        ExportFormat::Wget => wget(parts)?,
+    };
+
+    let cmd = lines.join(" \\\n  ");
+    Ok(match stdin {
+        Some(data) => format!("printf '%s' {} | base64 -d | {}\n", quote(data.trim()), cmd),
+        None => format!("{}\n", cmd),
+    })
+}
+
+fn curl(parts: Parts) -> Result<Command> {
+    let mut cmd = Command::default();
+    // curl sends a POST when there's a body, e.g. for a GET with a body
+    cmd.lines.push(match (parts.method, &parts.body) {
+        (&Method::GET, None) => format!("curl {}", parts.url),
+        (&Method::HEAD, None) => format!("curl --head {}", parts.url),
+        (method, _) => format!("curl -X {} {}", method, parts.url),
+    });
+    if let Some(socket) = &parts.unix_socket {
+        cmd.lines.push(format!(
+            "--unix-socket {}",
+            quote(&socket.to_string_lossy())
+        ));
+    }
+    for (header, secret) in &parts.headers {
+        cmd.lines.push(format!("-H {}", word(header, *secret)));
+    }
+    if let Some(user_agent) = parts.user_agent {
+        cmd.lines.push(format!("-A {}", quote(user_agent)));
+    }
+    if let Some((username, password)) = parts.basic {
+        cmd.lines
+            .push(format!("-u {}", word(&format!("{}:", username), password)));
+    }
+    match parts.body {
+        Some(Body::Text(data)) => cmd.lines.push(format!("--data-raw {}", quote(&data))),
+        Some(Body::File(path)) => {
+            let arg = format!("@{}", path.to_string_lossy());
+            cmd.lines.push(format!("--data-binary {}", quote(&arg)));
+        }
+        Some(Body::Base64(data)) => {
+            cmd.stdin = Some(data);
+            cmd.lines.push("--data-binary @-".to_string());
+        }
+        Some(Body::Multipart(fields)) => {
+            for (name, value) in fields {
+                cmd.lines.push(match &value {
+                    Value::Object(file) => {
+                        let mut arg = format!("{}=@{}", name, field(file, "file"));
+                        for (key, option) in [("filename", "filename"), ("type", "content_type")] {
+                            if file.contains_key(option) {
+                                arg.push_str(&format!(";{}={}", key, field(file, option)));
+                            }
+                        }
+                        format!("-F {}", quote(&arg))
+                    }
+                    value => format!(
+                        "--form-string {}",
+                        quote(&format!("{}={}", name, text(value)))
+                    ),
+                });
+            }
+        }
+        None => {}
+    }
+    Ok(cmd)
+}
+
+fn httpie(parts: Parts) -> Result<Command> {
+    let mut cmd = Command::default();
+    cmd.lines
+        .push(format!("http {} {}", parts.method, parts.url));
+    for (header, secret) in &parts.headers {
+        // httpie headers are `name:value`
+        let header = header.replacen(": ", ":", 1);
+        cmd.lines.push(word(&header, *secret));
+    }
+    if let Some(user_agent) = parts.user_agent {
+        cmd.lines.push(quote(&format!("user-agent:{}", user_agent)));
+    }
+    if let Some((username, password)) = parts.basic {
+        cmd.lines.push(format!(
+            "--auth {}",
+            word(&format!("{}:", username), password)
+        ));
+    }
+    match parts.body {
+        Some(Body::Text(data)) => cmd.lines.push(format!("--raw {}", quote(&data))),
+        Some(Body::File(path)) => cmd
+            .lines
+            .push(format!("< {}", quote(&path.to_string_lossy()))),
+        Some(Body::Base64(data)) => cmd.stdin = Some(data),
+        Some(Body::Multipart(fields)) => {
+            cmd.lines.push("--multipart".to_string());
+            for (name, value) in fields {
+                cmd.lines.push(match &value {
+                    Value::Object(file) => {
+                        let mut arg = format!("{}@{}", name, field(file, "file"));
+                        if file.contains_key("content_type") {
+                            arg.push_str(&format!(";type={}", field(file, "content_type")));
+                        }
+                        quote(&arg)
+                    }
+                    value => quote(&format!("{}={}", name, httpie_text(&text(value)))),
+                });
+            }
+        }
+        None => {}
+    }
+    Ok(cmd)
+}
+
+/// Escape a text field value which httpie would read as part of another separator, e.g. `@req`
+/// in `name=@req` which would be sent as the content of the file `req`.
+fn httpie_text(value: &str) -> String {
+    match value.starts_with(['@', '=', ':']) {
+        true => format!("\\{}", value),
+        false => value.to_string(),
+    }
+}
+
+fn wget(parts: Parts) -> Result<Command> {
+    let mut cmd = Command::default();
+    let method = match (parts.method, &parts.body) {
+        (&Method::GET, None) => String::new(),
+        (method, _) => format!(" --method={}", method),
+    };
+    cmd.lines
+        .push(format!("wget -q -O -{} {}", method, parts.url));
+    for (header, secret) in &parts.headers {
+        cmd.lines
+            .push(word(&format!("--header={}", header), *secret));
+    }
+    if let Some(user_agent) = parts.user_agent {
+        cmd.lines
+            .push(quote(&format!("--user-agent={}", user_agent)));
+    }
+    if let Some((username, password)) = parts.basic {
+        cmd.lines.push("--auth-no-challenge".to_string());
+        cmd.lines.push(quote(&format!("--user={}", username)));
+        cmd.lines.push(word("--password=", password));
+    }
+    match parts.body {
+        Some(Body::Text(data)) => cmd.lines.push(quote(&format!("--body-data={}", data))),
+        Some(Body::File(path)) => cmd
+            .lines
+            .push(quote(&format!("--body-file={}", path.to_string_lossy()))),
+        Some(Body::Base64(data)) => {
+            cmd.stdin = Some(data);
+            cmd.lines.push("--body-file=/dev/stdin".to_string());
+        }
+        Some(Body::Multipart(_)) => return Err(anyhow!("wget doesn't support multipart bodies")),
+        None => {}
+    }
+    Ok(cmd)
+}
+
+fn multipart_fields(body: &Value) -> Result<Vec<(String, Value)>> {
+    let fields = body
+        .as_object()
+        .ok_or_else(|| anyhow!("multipart body must be an object"))?;
+    let mut result = Vec::new();
+    for (name, value) in fields {
+        let values = match value {
+            Value::Array(values) => values.clone(),
+            value => vec![value.clone()],
+        };
+        for value in values {
+            if let Value::Object(file) = &value {
+                if !file.contains_key("file") {
+                    return Err(anyhow!("multipart field {} must have a file path", name));
+                }
+            }
+            result.push((name.clone(), value));
+        }
+    }
+    Ok(result)
+}
+
+fn field<'a>(file: &'a serde_json::Map<String, Value>, key: &str) -> &'a str {
+    file.get(key).and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Quote the word for POSIX shells, unless it's made of characters which are safe as they are.
+fn quote(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@,%+".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// A shell word of the text followed by the secret. Secrets from an environment variable or a
+/// file are expanded by the shell, so they aren't written in the command.
+fn word(text: &str, secret: Option<&Secret>) -> String {
+    match secret {
+        None => quote(text),
+        Some(Secret::Value(value)) => quote(&format!("{}{}", text, value)),
+        Some(Secret::Env { env }) => format!("{}\"${{{}}}\"", quote(text), env),
+        Some(Secret::File { file }) => {
+            format!(
+                "{}\"$(cat {})\"",
+                quote(text),
+                quote(&file.to_string_lossy())
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> RequestContext {
+        let mut ctx: RequestContext = "https://api.example.com/orders?page=2".parse().unwrap();
+        ctx.method = Method::POST;
+        ctx.headers.insert("x-note", "it's".parse().unwrap());
+        ctx.body = Some(serde_json::json!({"id": 1}));
+        ctx.auth = Some(Auth::Bearer {
+            token: Secret::Env {
+                env: "API_TOKEN".into(),
+            },
+        });
+        ctx
+    }
+
+    #[test]
+    fn export_should_render_commands() {
+        let ctx = request();
+        assert_eq!(
+            ctx.export(ExportFormat::Curl).unwrap(),
+            r#"curl -X POST 'https://api.example.com/orders?page=2' \
+  -H 'x-note: it'\''s' \
+  -H 'content-type: application/json' \
+  -H 'authorization: Bearer '"${API_TOKEN}" \
+  --data-raw '{"id":1}'
+"#
+        );
+        assert_eq!(
+            ctx.export(ExportFormat::Httpie).unwrap(),
+            r#"http POST 'https://api.example.com/orders?page=2' \
+  'x-note:it'\''s' \
+  content-type:application/json \
+  'authorization:Bearer '"${API_TOKEN}" \
+  --raw '{"id":1}'
+"#
+        );
+        assert_eq!(
+            ctx.export(ExportFormat::Wget).unwrap(),
+            r#"wget -q -O - --method=POST 'https://api.example.com/orders?page=2' \
+  '--header=x-note: it'\''s' \
+  '--header=content-type: application/json' \
+  '--header=authorization: Bearer '"${API_TOKEN}" \
+  '--body-data={"id":1}'
+"#
+        );
+    }
+
+    #[test]
+    fn exported_curl_should_parse_back() {
+        let mut ctx = request();
+        ctx.auth = Some(Auth::Basic {
+            username: "alice".into(),
+            password: Some(Secret::Value("secret".into())),
+        });
+        ctx.user_agent = Some("xreq test".into());
+        let parsed = RequestContext::from_curl(&ctx.export(ExportFormat::Curl).unwrap()).unwrap();
+        assert_eq!(parsed.method, ctx.method);
+        assert_eq!(parsed.url, ctx.url);
+        assert_eq!(parsed.params, ctx.params);
+        assert_eq!(parsed.headers["x-note"], "it's");
+        assert_eq!(parsed.body, ctx.body);
+        // the password isn't imported into the profile
+        assert_eq!(
+            parsed.auth,
+            Some(Auth::Basic {
+                username: "alice".into(),
+                password: Some(Secret::Env {
+                    env: "XREQ_PASSWORD".into()
+                }),
+            })
+        );
+        assert_eq!(parsed.user_agent, ctx.user_agent);
+
+        let mut get = ctx.clone();
+        get.method = Method::GET;
+        let parsed = RequestContext::from_curl(&get.export(ExportFormat::Curl).unwrap()).unwrap();
+        assert_eq!(parsed.method, Method::GET);
+        assert_eq!(parsed.body, get.body);
+
+        ctx.headers
+            .insert(CONTENT_TYPE, "multipart/form-data".parse().unwrap());
+        ctx.body = Some(serde_json::json!({
+            "name": "@req",
+            "file": { "file": "fixtures/req.yml", "content_type": "text/yaml" },
+        }));
+        let cmd = ctx.export(ExportFormat::Curl).unwrap();
+        assert!(cmd.contains("--form-string name=@req"));
+        assert!(cmd.contains("-F 'file=@fixtures/req.yml;type=text/yaml'"));
+        let cmd = ctx.export(ExportFormat::Httpie).unwrap();
+        assert!(cmd.contains(r"'name=\@req'"));
+        assert!(ctx.export(ExportFormat::Wget).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! A small subset of JSONPath, used to address parts of a response body.
+//!
+//! Supported syntax:
+//! - `$`: the root
+//! - `.name`, `['name']`, `["name"]`: an object member
+//! - `[3]`: an array element
+//! - `.*`, `[*]`: any member or element
+//! - `..name`, `..*`, `..[3]`: recursive descent
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JsonPath {
+    selectors: Vec<Selector>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selector {
+    /// matches the step on the direct child
+    Child(Step),
+    /// matches the step on any descendant
+    Descendant(Step),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+/// One element of a concrete location inside a JSON document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PathElem {
+    Key(String),
+    Index(usize),
+    /// an array element identified by the value of one of its members
+    Match {
+        key: String,
+        value: Value,
+    },
+    /// an element of an unordered array, by its index in the old array
+    OldIndex(usize),
+    /// an element of an unordered array, by its index in the new array
+    NewIndex(usize),
+}
+
+impl JsonPath {
+    /// Check if the concrete location (relative to the root) is selected by this path.
+    pub fn matches(&self, location: &[PathElem]) -> bool {
+        matches(&self.selectors, location)
+    }
+
+    /// Remove every node selected by this path from the value.
+    pub fn remove(&self, value: &mut Value) {
+        let mut location = Vec::new();
+        self.remove_inner(value, &mut location);
+    }
+
+    fn remove_inner(&self, value: &mut Value, location: &mut Vec<PathElem>) {
+        match value {
+            Value::Object(map) => {
+                let keys: Vec<String> = map.keys().cloned().collect();
+                for k in keys {
+                    location.push(PathElem::Key(k.clone()));
+                    if self.matches(location) {
+                        map.remove(&k);
+                    } else if let Some(v) = map.get_mut(&k) {
+                        self.remove_inner(v, location);
+                    }
+                    location.pop();
+                }
+            }
+            Value::Array(arr) => {
+                let mut idx = 0;
+                let mut i = 0;
+                while i < arr.len() {
+                    location.push(PathElem::Index(idx));
+                    if self.matches(location) {
+                        arr.remove(i);
+                    } else {
+                        self.remove_inner(&mut arr[i], location);
+                        i += 1;
+                    }
+                    location.pop();
+                    idx += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Format a concrete location as a normalized JSONPath, e.g. `$.items[3].price`.
+pub(crate) fn format_location(location: &[PathElem]) -> String {
+    location.iter().fold("$".to_string(), |mut acc, elem| {
+        acc.push_str(&elem.to_string());
+        acc
+    })
+}
+
+fn matches(selectors: &[Selector], location: &[PathElem]) -> bool {
+    match selectors.split_first() {
+        None => location.is_empty(),
+        Some((Selector::Child(step), rest)) => match location.split_first() {
+            Some((elem, location)) => step.matches(elem) && matches(rest, location),
+            None => false,
+        },
+        Some((Selector::Descendant(step), rest)) => (0..location.len())
+            .any(|i| step.matches(&location[i]) && matches(rest, &location[i + 1..])),
+    }
+}
+
+impl Step {
+    fn matches(&self, elem: &PathElem) -> bool {
+        match (self, elem) {
+            (Step::Wildcard, _) => true,
+            (Step::Key(k1), PathElem::Key(k2)) => k1 == k2,
+            (Step::Index(i1), PathElem::Index(i2)) => i1 == i2,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let err = |msg: &str| anyhow!("invalid JSONPath {:?}: {}", s, msg);
+        let rest = s
+            .trim()
+            .strip_prefix('$')
+            .ok_or_else(|| err("must start with '$'"))?;
+        let chars: Vec<char> = rest.chars().collect();
+        let mut selectors = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let descendant = chars[i..].starts_with(&['.', '.']);
+            let step = if descendant && chars.get(i + 2) == Some(&'[') {
+                i += 2;
+                let (step, next) = parse_bracket(&chars, i).map_err(|e| err(&e))?;
+                i = next;
+                step
+            } else if chars[i] == '.' {
+                i += if descendant { 2 } else { 1 };
+                let start = i;
+                while i < chars.len() && chars[i] != '.' && chars[i] != '[' {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+                match name.as_str() {
+                    "" => return Err(err("empty member name")),
+                    "*" => Step::Wildcard,
+                    _ => Step::Key(name),
+                }
+            } else if chars[i] == '[' {
+                let (step, next) = parse_bracket(&chars, i).map_err(|e| err(&e))?;
+                i = next;
+                step
+            } else {
+                return Err(err(&format!("unexpected character '{}'", chars[i])));
+            };
+            selectors.push(if descendant {
+                Selector::Descendant(step)
+            } else {
+                Selector::Child(step)
+            });
+        }
+        Ok(Self { selectors })
+    }
+}
+
+/// Parse a bracketed step starting at `chars[start] == '['`, return the step and the next position.
+fn parse_bracket(chars: &[char], start: usize) -> std::result::Result<(Step, usize), String> {
+    let mut i = start + 1;
+    match chars.get(i) {
+        Some(&quote) if quote == '\'' || quote == '"' => {
+            let mut name = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('\\') => {
+                        name.extend(chars.get(i + 1));
+                        i += 2;
+                    }
+                    Some(&c) if c == quote => break,
+                    Some(&c) => {
+                        name.push(c);
+                        i += 1;
+                    }
+                    None => return Err("unterminated quoted name".into()),
+                }
+            }
+            if chars.get(i + 1) != Some(&']') {
+                return Err("expected ']'".into());
+            }
+            Ok((Step::Key(name), i + 2))
+        }
+        _ => {
+            let end = chars[i..]
+                .iter()
+                .position(|c| *c == ']')
+                .ok_or_else(|| "expected ']'".to_string())?;
+            let inner: String = chars[i..i + end].iter().collect();
+            let step = match inner.trim() {
+                "*" => Step::Wildcard,
+                n => Step::Index(
+                    n.parse()
+                        .map_err(|_| format!("invalid array index '{}'", n))?,
+                ),
+            };
+            Ok((step, i + end + 1))
+        }
+    }
+}
+
+impl fmt::Display for PathElem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathElem::Index(i) => write!(f, "[{}]", i),
+            PathElem::Match { key, value } => write!(f, "[?(@.{}=={})]", key, value),
+            PathElem::OldIndex(i) => write!(f, "[old:{}]", i),
+            PathElem::NewIndex(i) => write!(f, "[new:{}]", i),
+            PathElem::Key(k) => {
+                let plain = !k.is_empty()
+                    && !k.starts_with(|c: char| c.is_ascii_digit())
+                    && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if plain {
+                    write!(f, ".{}", k)
+                } else {
+                    write!(f, "['{}']", k.replace('\\', "\\\\").replace('\'', "\\'"))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn jsonpath_should_parse_and_match() {
+        let key = |k: &str| PathElem::Key(k.into());
+        let path: JsonPath = "$.data[*].updated_at".parse().unwrap();
+        assert!(path.matches(&[key("data"), PathElem::Index(3), key("updated_at")]));
+        assert!(!path.matches(&[key("data"), key("updated_at")]));
+
+        let path: JsonPath = "$..trace_id".parse().unwrap();
+        assert!(path.matches(&[key("trace_id")]));
+        assert!(path.matches(&[key("a"), PathElem::Index(0), key("trace_id")]));
+        assert!(!path.matches(&[key("trace_id"), key("a")]));
+
+        let path: JsonPath = "$['x-y'][0]".parse().unwrap();
+        assert!(path.matches(&[key("x-y"), PathElem::Index(0)]));
+
+        assert!("data".parse::<JsonPath>().is_err());
+        assert!("$.a[x]".parse::<JsonPath>().is_err());
+        assert!("$.a[".parse::<JsonPath>().is_err());
+    }
+
+    #[test]
+    fn jsonpath_remove_should_work() {
+        let mut v = json!({
+            "meta": {"request_id": "abc", "page": 1},
+            "data": [{"id": 1, "updated_at": "x", "trace_id": 1}, {"id": 2, "updated_at": "y"}],
+            "trace_id": 2
+        });
+        for p in ["$.meta.request_id", "$.data[*].updated_at", "$..trace_id"] {
+            p.parse::<JsonPath>().unwrap().remove(&mut v);
+        }
+        assert_eq!(
+            v,
+            json!({"meta": {"page": 1}, "data": [{"id": 1}, {"id": 2}]})
+        );
+
+        let mut v = json!([1, 2, 3]);
+        "$[1]".parse::<JsonPath>().unwrap().remove(&mut v);
+        assert_eq!(v, json!([1, 3]));
+    }
+}
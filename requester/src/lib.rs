@@ -1,7 +1,31 @@
+mod auth;
+mod body;
+mod cassette;
+mod client;
+mod config;
+mod curl;
 mod diff;
+mod export;
+mod jsonpath;
 mod req;
+#[cfg(test)]
+mod test_server;
+mod unix;
 
-pub use diff::{DiffConfig, DiffContext, DiffResult, ResponseContext};
+pub use auth::{
+    ApiKeyLocation, Auth, AwsSigV4Auth, GrantType, HmacAlgorithm, HmacAuth, OAuth2Auth, Secret,
+    SignatureEncoding,
+};
+pub use body::BodyType;
+pub use cassette::{Cassette, CassetteMode};
+pub use client::{ClientConfig, TlsConfig, TlsVersion};
+pub use config::LoadOptions;
+pub use diff::{
+    ArrayRule, BodyDiff, CompareRule, DiffConfig, DiffContext, DiffLine, DiffMode, DiffReport,
+    DiffResult, HeaderChange, Hunk, JsonChange, LatencyRegression, LineTag, MaskRule,
+    ResponseContext, Snapshot, StatusChange, Timing,
+};
+pub use export::ExportFormat;
 pub use req::{RequestConfig, RequestContext};
 
 // re-exports
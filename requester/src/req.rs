@@ -1,17 +1,39 @@
-use std::{collections::HashMap, path::Path, str::FromStr};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
 
 use anyhow::Result;
-use http::{header::HeaderName, HeaderMap, HeaderValue, Method};
-use reqwest::{Client, Response};
+use http::{
+    header::{HeaderName, CONTENT_TYPE},
+    HeaderMap, HeaderValue, Method, StatusCode,
+};
+use reqwest::{Client, Request, Response};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::fs;
 use url::Url;
 
-use crate::{KeyVal, KeyValType};
+use crate::{
+    auth::Auth,
+    body::{self, BodyType},
+    cassette::{Cassette, CassetteMode},
+    client::{self, ClientConfig},
+    config::{self, ConfigKind},
+    curl,
+    export::{self, ExportFormat},
+    unix, KeyVal, KeyValType, LoadOptions,
+};
 
 const USER_AGENT: &str = "Requester/0.1.0";
 
+/// When the attempt which got a response was sent, kept in the extensions of the response so
+/// that the latency doesn't include the failed attempts before it and their backoff.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AttemptStart(pub Instant);
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct RequestConfig {
     #[serde(flatten)]
@@ -27,6 +49,10 @@ pub struct RequestContext {
     )]
     pub method: Method,
     pub url: Url,
+    /// send the request over this unix domain socket. `unix:///path/to/app.sock:/path` and
+    /// `unix://%2Fpath%2Fto%2Fapp.sock/path` urls are supported as well.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub unix_socket: Option<PathBuf>,
     #[serde(skip_serializing_if = "is_empty_value", default = "default_params")]
     pub params: Value,
     #[serde(skip_serializing_if = "HeaderMap::is_empty", default)]
@@ -34,8 +60,20 @@ pub struct RequestContext {
     pub headers: HeaderMap,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub body: Option<Value>,
+    /// how to encode the body, inferred from the content-type header if not given
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub body_type: Option<BodyType>,
+    /// read the body from this file (relative to the config file) instead of `body`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub body_file: Option<PathBuf>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub user_agent: Option<String>,
+    /// credentials added to the request when it's sent
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub auth: Option<Auth>,
+    /// timeouts, retries and other settings of the HTTP client
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub client: ClientConfig,
 }
 
 fn is_default<T: Default + PartialEq>(t: &T) -> bool {
@@ -58,15 +96,22 @@ impl RequestConfig {
     }
 
     pub async fn try_load(path: impl AsRef<Path>) -> Result<Self> {
-        let file = fs::read_to_string(path).await?;
-        let config: Self = serde_yaml::from_str(&file)?;
-        for (profile, ctx) in config.ctxs.iter() {
+        Self::try_load_with(path, &LoadOptions::default()).await
+    }
+
+    pub async fn try_load_with(path: impl AsRef<Path>, opts: &LoadOptions) -> Result<Self> {
+        let (value, _) = config::load_yaml(path.as_ref(), opts, ConfigKind::Request).await?;
+        let mut config: Self = serde_yaml::from_value(value)?;
+        for (profile, ctx) in config.ctxs.iter_mut() {
             if !ctx.params.is_object() {
                 return Err(anyhow::anyhow!(
                     "params must be an object in profile: {}",
                     profile
                 ));
             }
+            ctx.validate()
+                .map_err(|e| anyhow::anyhow!("{} in profile: {}", e, profile))?;
+            ctx.resolve_paths(path.as_ref());
         }
         Ok(config)
     }
@@ -102,8 +147,23 @@ impl RequestContext {
                     );
                 }
                 KeyValType::Body => {
-                    if let Some(body) = self.body.as_mut() {
-                        body[&v.key] = serde_json::Value::String(v.val.to_owned())
+                    if self.body_file.is_some() {
+                        return Err(anyhow::anyhow!(
+                            "body overrides can't be used with body_file"
+                        ));
+                    }
+                    match self
+                        .body
+                        .get_or_insert_with(|| Value::Object(Default::default()))
+                    {
+                        Value::Object(body) => {
+                            body.insert(v.key.clone(), Value::String(v.val.to_owned()));
+                        }
+                        _ => {
+                            return Err(anyhow::anyhow!(
+                                "body overrides need a JSON/form object body"
+                            ))
+                        }
                     }
                 }
             }
@@ -112,47 +172,189 @@ impl RequestContext {
         Ok(())
     }
 
+    /// Set the body from a string, e.g. read from stdin. It's parsed as JSON for the `json` and
+    /// `multipart` body types, and used as is for the others.
+    pub fn set_body(&mut self, body: &str) -> Result<()> {
+        let body = match self.body_type() {
+            BodyType::Json | BodyType::Multipart => serde_json::from_str(body)
+                .map_err(|e| anyhow::anyhow!("body is not valid JSON: {}", e))?,
+            _ => Value::String(body.to_string()),
+        };
+        self.body = Some(body);
+        self.body_file = None;
+        Ok(())
+    }
+
+    /// Record the responses to the file, or replay them from it. The `match_headers` of the
+    /// cassette in the config file are kept.
+    pub fn set_cassette(&mut self, file: PathBuf, mode: CassetteMode) {
+        let cassette = self
+            .client
+            .cassette
+            .get_or_insert_with(|| Cassette::new(&file, mode));
+        cassette.file = file;
+        cassette.mode = mode;
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.body.is_some() && self.body_file.is_some() {
+            return Err(anyhow::anyhow!("body and body_file can't be used together"));
+        }
+        self.client.validate()
+    }
+
+    /// Make the relative file paths relative to the directory of the config file.
+    pub(crate) fn resolve_paths(&mut self, config_file: &Path) {
+        let dir = config_file.parent().unwrap_or_else(|| Path::new(""));
+        if let Some(path) = self.body_file.as_mut() {
+            if path.is_relative() {
+                *path = dir.join(&path);
+            }
+        }
+        if self.body_type() == BodyType::Multipart {
+            if let Some(body) = self.body.as_mut() {
+                body::resolve_paths(body, dir);
+            }
+        }
+        if let Some(auth) = self.auth.as_mut() {
+            auth.resolve_paths(dir);
+        }
+        self.client.tls.resolve_paths(dir);
+        if let Some(cassette) = self.client.cassette.as_mut() {
+            cassette.resolve_path(dir);
+        }
+    }
+
     pub async fn send(&self) -> Result<Response> {
+        match &self.client.cassette {
+            Some(cassette) => match cassette.mode {
+                CassetteMode::Replay => cassette.replay(self).await,
+                CassetteMode::Record => cassette.record(self, self.execute().await?).await,
+            },
+            None => self.execute().await,
+        }
+    }
+
+    /// Send the request over the network.
+    async fn execute(&self) -> Result<Response> {
+        let user_agent = self.user_agent.as_deref().unwrap_or(USER_AGENT);
+        let mut ctx = Cow::Borrowed(self);
+        let mut unix_socket = self.unix_socket.clone();
+        if self.url.scheme() == "unix" {
+            let (socket, url) = unix::split_url(&self.url)?;
+            unix_socket = Some(socket);
+            ctx.to_mut().url = url;
+        }
+
+        let mut resolve = self.client.resolve_overrides(&ctx.url)?;
+        let mut server_name = None;
+        if unix_socket.is_none() {
+            let tls = &self.client.tls;
+            if let Some((name, addrs)) = tls.server_name_override(&ctx.url, &resolve).await? {
+                resolve.push((name.clone(), addrs));
+                server_name = Some(name);
+            }
+        }
+        let client = self.client.client(user_agent, resolve)?;
+
+        let mut attempt = 0;
+        let mut token_rejected = false;
+        loop {
+            // the request is built again for each attempt, so the signatures are fresh
+            let mut req = ctx.build_request(&client).await?;
+            if let Some(name) = &server_name {
+                client::route_to_server_name(&mut req, name)?;
+            }
+            let start = Instant::now();
+            let res = match &unix_socket {
+                Some(socket) => unix::execute(socket, req, &self.client, user_agent).await,
+                None => client.execute(req).await.map_err(Into::into),
+            };
+            // a cached OAuth2 token may have been revoked before it expired, it's fetched again
+            // once
+            if let (Some(Auth::OAuth2(oauth2)), Ok(res)) = (&self.auth, &res) {
+                if res.status() == StatusCode::UNAUTHORIZED && !token_rejected {
+                    oauth2.invalidate().await?;
+                    token_rejected = true;
+                    continue;
+                }
+            }
+            match self.client.retry_delay(attempt, &ctx.method, &res) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => {
+                    let mut res = match &server_name {
+                        Some(name) => client::restore_host(res?, name, &ctx.url)?,
+                        None => res?,
+                    };
+                    res.extensions_mut().insert(AttemptStart(start));
+                    return Ok(res);
+                }
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Parse a curl command, e.g. one copied from the browser's devtools.
+    pub fn from_curl(cmd: &str) -> Result<Self> {
+        curl::parse(cmd)
+    }
+
+    /// Render the request as a command of the format, e.g. to hand a reproduction to someone who
+    /// doesn't use xreq.
+    pub fn export(&self, format: ExportFormat) -> Result<String> {
+        export::render(self, format)
+    }
+
+    /// The body type to use: the explicit `body_type`, or the one matching the content-type.
+    pub fn body_type(&self) -> BodyType {
+        self.body_type
+            .unwrap_or_else(|| BodyType::from_content_type(self.headers.get(CONTENT_TYPE)))
+    }
+
+    /// The url with the params as its query string.
+    pub(crate) fn url_with_params(&self) -> Result<Url> {
         let mut url = self.url.clone();
-        let user_agent = self
-            .user_agent
-            .clone()
-            .unwrap_or_else(|| USER_AGENT.to_string());
-        match url.scheme() {
+        let qs = serde_qs::to_string(&self.params)?;
+        if !qs.is_empty() {
+            url.set_query(Some(&qs));
+        }
+        Ok(url)
+    }
+
+    async fn build_request(&self, client: &Client) -> Result<Request> {
+        match self.url.scheme() {
             "http" | "https" => {
-                let qs = serde_qs::to_string(&self.params)?;
-                if !qs.is_empty() {
-                    url.set_query(Some(&qs));
+                let url = self.url_with_params()?;
+                let body_type = self.body_type();
+                let mut headers = self.headers.clone();
+                if self.body.is_some() && body_type == BodyType::Multipart {
+                    // the boundary is generated by the client, so it sets the content-type itself
+                    headers.remove(CONTENT_TYPE);
                 }
-                let client = Client::builder().user_agent(user_agent).build()?;
-
                 let mut builder = client
                     .request(self.method.clone(), url)
-                    .headers(self.headers.clone());
-
-                if let Some(body) = &self.body {
-                    match self.headers.get(http::header::CONTENT_TYPE) {
-                        Some(content_type) => {
-                            if content_type.to_str().unwrap().contains("application/json") {
-                                builder = builder.json(body);
-                            } else {
-                                return Err(anyhow::anyhow!(
-                                    "unsupported content-type: {:?}",
-                                    content_type
-                                ));
-                            }
-                        }
-                        None => {
-                            // TODO (tchen): here we just assume the content-type is json
-                            builder = builder.json(body)
+                    .headers(headers.clone());
+
+                if let Some(path) = &self.body_file {
+                    let data = tokio::fs::read(path).await.map_err(|e| {
+                        anyhow::anyhow!("failed to read body file {}: {}", path.display(), e)
+                    })?;
+                    if !headers.contains_key(CONTENT_TYPE) {
+                        if let Some(mime) = mime_guess::from_path(path).first_raw() {
+                            builder = builder.header(CONTENT_TYPE, mime);
                         }
                     }
-                    builder = builder.body(serde_json::to_string(body)?);
+                    builder = builder.body(data);
+                } else if let Some(body) = &self.body {
+                    builder = body::apply_body(builder, &headers, body_type, body).await?;
                 }
 
-                let res = builder.send().await?;
-
-                Ok(res)
+                let mut req = builder.build()?;
+                if let Some(auth) = &self.auth {
+                    let user_agent = self.user_agent.as_deref().unwrap_or(USER_AGENT);
+                    auth.apply(&mut req, &self.client, user_agent).await?;
+                }
+                Ok(req)
             }
             _ => Err(anyhow::anyhow!("unsupported scheme")),
         }
@@ -167,43 +369,188 @@ impl FromStr for RequestContext {
         let qs = url.query_pairs();
         let mut params = serde_json::Value::Object(Default::default());
         for (k, v) in qs {
-            let v = serde_json::Value::String(v.to_string());
-            match params.get_mut(&*k) {
-                Some(val) => {
-                    if val.is_string() {
-                        params[&*k] = serde_json::Value::Array(vec![val.clone(), v]);
-                    } else if val.is_array() {
-                        val.as_array_mut().unwrap().push(v);
-                    } else {
-                        panic!("unexpected value: {:?}", val);
-                    }
-                }
-                None => {
-                    params[&*k] = v;
-                }
-            }
+            append_param(&mut params, &k, &v);
         }
 
         url.set_query(None);
         Ok(RequestContext {
             method: Method::GET,
             url,
+            unix_socket: None,
             params,
             headers: HeaderMap::new(),
             body: None,
+            body_type: None,
+            body_file: None,
             user_agent: None,
+            auth: None,
+            client: ClientConfig::default(),
         })
     }
 }
 
+/// Add a query param, a repeated key becomes an array of the values.
+pub(crate) fn append_param(params: &mut Value, key: &str, value: &str) {
+    let v = serde_json::Value::String(value.to_string());
+    match params.get_mut(key) {
+        Some(val) => {
+            if val.is_string() {
+                params[key] = serde_json::Value::Array(vec![val.clone(), v]);
+            } else if val.is_array() {
+                val.as_array_mut().unwrap().push(v);
+            } else {
+                panic!("unexpected value: {:?}", val);
+            }
+        }
+        None => {
+            params[key] = v;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_server::{serve, Reply};
+
+    #[tokio::test]
+    async fn load_with_vars_should_work() {
+        std::env::set_var("XREQ_TEST_TOKEN", "secret");
+        let opts = LoadOptions::new([("id".to_string(), "2".to_string())].into());
+        let config = RequestConfig::try_load_with("fixtures/vars.yml", &opts)
+            .await
+            .unwrap();
+        let ctx = config.get("todo").unwrap();
+        assert_eq!(
+            ctx.url.as_str(),
+            "https://jsonplaceholder.typicode.com/todos/2"
+        );
+        assert_eq!(ctx.headers["authorization"], "Bearer secret");
+        assert_eq!(ctx.params["limit"], "10");
+
+        let err = RequestConfig::try_load("fixtures/vars.yml")
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "unresolved variable: id in profile: todo");
+    }
+
+    #[tokio::test]
+    async fn build_request_should_encode_multipart_body() {
+        let mut ctx: RequestContext = "http://localhost/upload".parse().unwrap();
+        ctx.method = Method::POST;
+        ctx.headers
+            .insert(CONTENT_TYPE, "multipart/form-data".parse().unwrap());
+        ctx.body = Some(serde_json::json!({
+            "name": "req",
+            "file": { "file": "fixtures/req.yml", "content_type": "text/yaml" },
+        }));
+        let req = ctx.build_request(&Client::new()).await.unwrap();
+        let content_type = req
+            .headers()
+            .get_all(CONTENT_TYPE)
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(content_type.len(), 1);
+        assert!(content_type[0]
+            .to_str()
+            .unwrap()
+            .starts_with("multipart/form-data; boundary="));
+    }
+
+    #[tokio::test]
+    async fn body_file_should_be_relative_to_config_file() {
+        let config = RequestConfig::try_load("fixtures/body.yml").await.unwrap();
+        let ctx = config.get("order").unwrap();
+        assert_eq!(
+            ctx.body_file.as_deref(),
+            Some(Path::new("fixtures/payloads/order.json"))
+        );
+        let req = ctx.build_request(&Client::new()).await.unwrap();
+        assert_eq!(req.headers()[CONTENT_TYPE], "application/json");
+        let body = req.body().unwrap().as_bytes().unwrap();
+        assert_eq!(body, std::fs::read("fixtures/payloads/order.json").unwrap());
+
+        let mut ctx = ctx.clone();
+        let kv = |v: &str| KeyVal::new(KeyValType::Body, "id", v);
+        assert!(ctx.update(&[kv("2")]).is_err());
+
+        ctx.set_body(r#"{"id": 2}"#).unwrap();
+        assert_eq!(ctx.body, Some(serde_json::json!({"id": 2})));
+        assert_eq!(ctx.body_file, None);
+        ctx.update(&[kv("3")]).unwrap();
+        assert_eq!(ctx.body, Some(serde_json::json!({"id": "3"})));
+
+        ctx.body = Some(serde_json::json!([1]));
+        let err = ctx.update(&[kv("3")]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "body overrides need a JSON/form object body"
+        );
+
+        let upload = config.get("upload").unwrap();
+        assert_eq!(
+            upload.body,
+            Some(serde_json::json!({
+                "name": "a",
+                "doc": {"file": "fixtures/payloads/order.json"},
+                "docs": [{"file": "/tmp/b.json"}],
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn send_should_retry_on_server_errors() {
+        // fails every other request, starting with the first one
+        let addr = serve(|req| match req.n % 2 {
+            1 => Reply::status(503),
+            _ => Reply::ok(""),
+        })
+        .await;
+
+        let mut ctx: RequestContext = format!("http://{}/", addr).parse().unwrap();
+        ctx.client = serde_yaml::from_str("retries: 1\nretry_backoff: 0.01").unwrap();
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        ctx.client.retries = 0;
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.status(), 503);
+    }
+
+    #[tokio::test]
+    async fn send_should_use_resolve_and_proxy() {
+        let addr = serve(|req| Reply::ok(req.lines(&["GET", "host:"]))).await;
+
+        let url = format!("http://api.example.test:{}/a", addr.port());
+        let mut ctx: RequestContext = url.parse().unwrap();
+        ctx.client.resolve.insert(
+            format!("api.example.test:{}", addr.port()),
+            "127.0.0.1".into(),
+        );
+        let body = ctx.send().await.unwrap().text().await.unwrap();
+        assert_eq!(
+            body,
+            format!("GET /a HTTP/1.1\nhost: api.example.test:{}", addr.port())
+        );
+
+        let mut ctx: RequestContext = "http://api.example.test/b".parse().unwrap();
+        ctx.client.proxy = Some(format!("http://{}", addr).parse().unwrap());
+        let body = ctx.send().await.unwrap().text().await.unwrap();
+        assert_eq!(
+            body,
+            "GET http://api.example.test/b HTTP/1.1\nhost: api.example.test"
+        );
+    }
 
     #[tokio::test]
     async fn send_request_should_work() {
         let config = RequestConfig::try_load("fixtures/req.yml").await.unwrap();
-        let result = config.send("rust").await.unwrap();
+        let mut ctx = config.get("rust").unwrap().clone();
+        ctx.client.cassette = Some(Cassette::new(
+            "fixtures/cassettes/rust.yml",
+            CassetteMode::Replay,
+        ));
+        let result = ctx.send().await.unwrap();
         assert_eq!(result.status(), 200);
     }
 }
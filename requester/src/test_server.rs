@@ -0,0 +1,174 @@
+//! A stand-in HTTP/1.1 server for the tests, so that they don't depend on the network.
+
+use http::StatusCode;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// A request received by the server.
+pub(crate) struct Request {
+    /// 1 for the first request served, 2 for the second one, etc.
+    pub n: usize,
+    /// the request line and the headers
+    pub head: String,
+    pub body: String,
+}
+
+/// The response to send back.
+pub(crate) struct Reply {
+    pub status: u16,
+    pub content_type: Option<&'static str>,
+    pub body: String,
+}
+
+impl Request {
+    /// The lines of the head starting with any of the prefixes, e.g. `["GET", "host:"]`, joined by
+    /// `\n`.
+    pub fn lines(&self, prefixes: &[&str]) -> String {
+        self.head
+            .lines()
+            .filter(|l| prefixes.iter().any(|p| l.starts_with(p)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Reply {
+    pub fn ok(body: impl Into<String>) -> Self {
+        Self::status(200).body(body)
+    }
+
+    pub fn json(body: impl Into<String>) -> Self {
+        Self {
+            content_type: Some("application/json"),
+            ..Self::ok(body)
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            content_type: None,
+            body: String::new(),
+        }
+    }
+
+    fn body(self, body: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            ..self
+        }
+    }
+}
+
+/// Serve the requests on a random local port with the handler, until the test ends.
+pub(crate) async fn serve<F>(handler: F) -> SocketAddr
+where
+    F: Fn(Request) -> Reply + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(handler);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            server.respond(stream).await;
+        }
+    });
+    addr
+}
+
+/// Serve the requests on a unix domain socket with the handler, until the test ends.
+#[cfg(unix)]
+pub(crate) fn serve_unix<F>(socket: &std::path::Path, handler: F)
+where
+    F: Fn(Request) -> Reply + Send + Sync + 'static,
+{
+    let listener = tokio::net::UnixListener::bind(socket).unwrap();
+    let server = Server::new(handler);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            server.respond(stream).await;
+        }
+    });
+}
+
+/// The handler and the number of requests served, for servers which accept the connections
+/// themselves, e.g. over TLS.
+#[derive(Clone)]
+pub(crate) struct Server {
+    handler: Arc<dyn Fn(Request) -> Reply + Send + Sync>,
+    served: Arc<AtomicUsize>,
+}
+
+impl Server {
+    pub fn new(handler: impl Fn(Request) -> Reply + Send + Sync + 'static) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            served: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Read a request from the connection, and write the reply of the handler. The connection is
+    /// closed afterwards.
+    pub async fn respond<S: AsyncRead + AsyncWrite + Unpin>(&self, mut stream: S) {
+        let Some((head, body)) = read_request(&mut stream).await else {
+            return;
+        };
+        let n = self.served.fetch_add(1, Ordering::SeqCst) + 1;
+        let reply = (self.handler)(Request { n, head, body });
+
+        let status = StatusCode::from_u16(reply.status).unwrap();
+        let mut res = format!(
+            "HTTP/1.1 {} {}\r\n",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or_default()
+        );
+        if let Some(content_type) = reply.content_type {
+            res.push_str(&format!("content-type: {}\r\n", content_type));
+        }
+        res.push_str(&format!(
+            "content-length: {}\r\nconnection: close\r\n\r\n{}",
+            reply.body.len(),
+            reply.body
+        ));
+        let _ = stream.write_all(res.as_bytes()).await;
+        let _ = stream.shutdown().await;
+    }
+}
+
+/// Read the head and the body (as long as its `content-length`) of a request.
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Option<(String, String)> {
+    let mut buf = Vec::new();
+    loop {
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let text = String::from_utf8_lossy(&buf);
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let len = head
+                .lines()
+                .find_map(|l| {
+                    l.to_lowercase()
+                        .strip_prefix("content-length: ")
+                        .and_then(|v| v.parse::<usize>().ok())
+                })
+                .unwrap_or(0);
+            if body.len() >= len {
+                return Some((head.to_string(), body.to_string()));
+            }
+        }
+    }
+}
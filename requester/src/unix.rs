@@ -0,0 +1,200 @@
+use crate::ClientConfig;
+use anyhow::{anyhow, Result};
+use http::{
+    header::{ACCEPT, HOST, USER_AGENT},
+    HeaderValue,
+};
+use percent_encoding::percent_decode_str;
+use reqwest::{Request, Response, ResponseBuilderExt};
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use url::Url;
+
+/// Split a unix socket url into the socket path and the http url of the request, e.g.
+/// `http://localhost/path?query`. The socket path is the percent-encoded host of
+/// `unix://%2Fvar%2Frun%2Fapp.sock/path?query`, or the path up to the first `:` of
+/// `unix:///var/run/app.sock:/path?query` (for sockets without a `:` in their path).
+pub(crate) fn split_url(url: &Url) -> Result<(PathBuf, Url)> {
+    let (socket, path) = match url.host_str() {
+        Some(host) if !host.is_empty() => {
+            let socket = percent_decode_str(host)
+                .decode_utf8()
+                .map_err(|_| anyhow!("invalid unix socket url: {}", url))?;
+            (socket.into_owned(), url.path())
+        }
+        _ => {
+            let (socket, path) = url.path().split_once(':').unwrap_or((url.path(), "/"));
+            (socket.to_string(), path)
+        }
+    };
+    if socket.is_empty() {
+        return Err(anyhow!("invalid unix socket url: {}", url));
+    }
+    let mut http_url: Url = "http://localhost/".parse()?;
+    http_url.set_path(path);
+    http_url.set_query(url.query());
+    Ok((PathBuf::from(socket), http_url))
+}
+
+/// Send the request over the unix domain socket with HTTP/1.1, and return the response with the
+/// whole body received. The request doesn't go through the reqwest client, so its user agent and
+/// timeouts are applied here. Redirects aren't followed.
+pub(crate) async fn execute(
+    socket: &Path,
+    req: Request,
+    config: &ClientConfig,
+    user_agent: &str,
+) -> Result<Response> {
+    within(config.timeout, send(socket, req, config, user_agent))
+        .await
+        .map_err(|_| anyhow!("request to unix socket {} timed out", socket.display()))?
+}
+
+/// Run the future, or fail with a `TimedOut` error if it takes longer than the timeout.
+async fn within<T>(timeout: Option<Duration>, fut: impl Future<Output = T>) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut)),
+        None => Ok(fut.await),
+    }
+}
+
+#[cfg(unix)]
+async fn send(
+    socket: &Path,
+    req: Request,
+    config: &ClientConfig,
+    user_agent: &str,
+) -> Result<Response> {
+    use http_body_util::BodyExt;
+    use hyper_util::rt::TokioIo;
+    use tokio::net::UnixStream;
+
+    let url = req.url().clone();
+    let mut req: http::Request<reqwest::Body> = req.try_into()?;
+    // the request line only has the path, the host header is the url's host
+    *req.uri_mut() = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+    .parse()?;
+    let host = url.host_str().unwrap_or("localhost");
+    // the same defaults as the reqwest client, except accept-encoding since the body isn't
+    // decompressed
+    for (name, value) in [(HOST, host), (USER_AGENT, user_agent), (ACCEPT, "*/*")] {
+        if !req.headers().contains_key(&name) {
+            req.headers_mut()
+                .insert(name, HeaderValue::from_str(value)?);
+        }
+    }
+
+    // a connect timeout is a connect error, so the request is retried
+    let stream = within(config.connect_timeout, UnixStream::connect(socket)).await??;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(conn);
+    let res = within(config.read_timeout, sender.send_request(req))
+        .await
+        .map_err(|_| anyhow!("reading from unix socket {} timed out", socket.display()))??;
+
+    let (parts, mut body) = res.into_parts();
+    let mut data = Vec::new();
+    while let Some(frame) = within(config.read_timeout, body.frame())
+        .await
+        .map_err(|_| anyhow!("reading from unix socket {} timed out", socket.display()))?
+    {
+        if let Ok(chunk) = frame?.into_data() {
+            data.extend_from_slice(&chunk);
+        }
+    }
+    let mut builder = http::Response::builder()
+        .status(parts.status)
+        .version(parts.version)
+        .url(url);
+    if let Some(headers) = builder.headers_mut() {
+        *headers = parts.headers;
+    }
+    Ok(Response::from(builder.body(data)?))
+}
+
+#[cfg(not(unix))]
+async fn send(
+    _socket: &Path,
+    _req: Request,
+    _config: &ClientConfig,
+    _user_agent: &str,
+) -> Result<Response> {
+    Err(anyhow!(
+        "unix domain sockets aren't supported on this platform"
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{
+        test_server::{serve_unix, Reply},
+        RequestContext,
+    };
+
+    #[test]
+    fn split_url_should_work() {
+        let url = "unix:///var/run/app.sock:/v1/stats?full=1".parse().unwrap();
+        let (socket, url) = split_url(&url).unwrap();
+        assert_eq!(socket, Path::new("/var/run/app.sock"));
+        assert_eq!(url.as_str(), "http://localhost/v1/stats?full=1");
+
+        let url = "unix:///var/run/app.sock".parse().unwrap();
+        let (socket, url) = split_url(&url).unwrap();
+        assert_eq!(socket, Path::new("/var/run/app.sock"));
+        assert_eq!(url.as_str(), "http://localhost/");
+
+        let url = "unix://%2Frun%2Fapp%3A1.sock/v1/stats?full=1"
+            .parse()
+            .unwrap();
+        let (socket, url) = split_url(&url).unwrap();
+        assert_eq!(socket, Path::new("/run/app:1.sock"));
+        assert_eq!(url.as_str(), "http://localhost/v1/stats?full=1");
+    }
+
+    #[tokio::test]
+    async fn send_should_support_unix_sockets() {
+        let socket = std::env::temp_dir().join(format!("xreq-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        serve_unix(&socket, |req| {
+            Reply::ok(req.lines(&["GET", "host:", "user-agent:", "accept:"]))
+        });
+
+        let url = format!("unix://{}:/stats?a=1", socket.display());
+        let ctx: RequestContext = url.parse().unwrap();
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.url().as_str(), "http://localhost/stats?a=1");
+        assert_eq!(
+            res.text().await.unwrap(),
+            "GET /stats?a=1 HTTP/1.1\nhost: localhost\nuser-agent: Requester/0.1.0\naccept: */*"
+        );
+
+        let encoded: String = percent_encoding::utf8_percent_encode(
+            &socket.to_string_lossy(),
+            percent_encoding::NON_ALPHANUMERIC,
+        )
+        .collect();
+        let ctx: RequestContext = format!("unix://{}/stats", encoded).parse().unwrap();
+        let res = ctx.send().await.unwrap();
+        assert_eq!(res.url().as_str(), "http://localhost/stats");
+
+        let mut ctx: RequestContext = "http://admin.local/ready".parse().unwrap();
+        ctx.unix_socket = Some(socket.clone());
+        ctx.user_agent = Some("xreq-test".into());
+        let res = ctx.send().await.unwrap();
+        assert_eq!(
+            res.text().await.unwrap(),
+            "GET /ready HTTP/1.1\nhost: admin.local\nuser-agent: xreq-test\naccept: */*"
+        );
+        let _ = std::fs::remove_file(&socket);
+    }
+}
@@ -1,9 +1,20 @@
 use anyhow::Result;
 use clap::Parser;
 use dialoguer::{theme::ColorfulTheme, Input, MultiSelect};
-use std::{io::Write, path::PathBuf};
-use xreq_cli_utils::{get_config_file, get_default_config, parse_key_val, print_syntect};
-use xreq_lib::{DiffConfig, DiffResult, KeyVal, RequestContext, ResponseContext};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+};
+use tokio::sync::Semaphore;
+use xreq_cli_utils::{
+    get_cassette, get_config_file, get_default_config, parse_key_val, parse_var, print_syntect,
+};
+use xreq_lib::{
+    DiffConfig, DiffContext, DiffResult, KeyVal, LoadOptions, RequestContext, ResponseContext,
+    Snapshot,
+};
 
 /// Diff API response.
 #[derive(Parser, Debug)]
@@ -16,13 +27,82 @@ struct Args {
 #[derive(clap::Subcommand, Debug, Clone)]
 enum Action {
     /// parse a URL and print the generated diff config.
-    Parse,
+    Parse(ParseArgs),
     /// diff two API responses based on a given profile.
-    Run(RunArgs),
+    Run(Box<RunArgs>),
+    /// send request1 of a profile and save its normalized response as a snapshot.
+    Snapshot(SnapshotArgs),
+    /// send request1 of a profile and diff the response against the saved snapshot.
+    Check(CheckArgs),
 }
 
 #[derive(Parser, Debug, Clone)]
+struct ParseArgs {
+    /// Two curl commands to parse instead of prompting for the URLs, e.g. ones copied from the
+    /// browser's devtools.
+    #[clap(long, value_parser, num_args = 2, value_names = ["CURL1", "CURL2"])]
+    from_curl: Vec<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(group(clap::ArgGroup::new("profiles").required(true).multiple(true)))]
 struct RunArgs {
+    /// API profile to use.
+    #[clap(short, long, value_parser, group = "profiles", conflicts_with_all = ["all", "profile_glob", "tag"])]
+    profile: Option<String>,
+
+    /// Run all the profiles in the config file.
+    #[clap(long, group = "profiles", conflicts_with_all = ["profile_glob", "tag"])]
+    all: bool,
+
+    /// Run the profiles whose name matches the glob pattern, e.g. 'catalog-*'.
+    #[clap(long, value_parser, group = "profiles")]
+    profile_glob: Option<glob::Pattern>,
+
+    /// Run the profiles with the tag. Could be given multiple times to select any of the tags.
+    #[clap(short, long, value_parser, group = "profiles")]
+    tag: Vec<String>,
+
+    /// Maximum number of profiles to run at the same time.
+    #[clap(long, value_parser, default_value_t = 8)]
+    concurrency: usize,
+
+    /// Extra parameters to pass to the API.
+    #[clap(short, value_parser = parse_key_val, number_of_values = 1)]
+    extra_params: Vec<KeyVal>,
+
+    /// Path to the config file.
+    #[clap(short, long, value_parser = get_config_file)]
+    config: Option<PathBuf>,
+
+    /// Variables for the `{{ key }}` placeholders in the config file, in the form of key=value.
+    /// They take precedence over the `vars` in the config file and the environment variables.
+    #[clap(long = "var", value_parser = parse_var, number_of_values = 1)]
+    vars: Vec<(String, String)>,
+
+    /// Environment for request1 of the profiles defined with a single `request` template.
+    #[clap(long, value_parser)]
+    left: Option<String>,
+
+    /// Environment for request2 of the profiles defined with a single `request` template.
+    #[clap(long, value_parser)]
+    right: Option<String>,
+
+    /// Output format of the diff result.
+    #[clap(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Send the requests and record the responses to the cassette file.
+    #[clap(long, value_parser, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve the responses from the cassette file instead of sending the requests.
+    #[clap(long, value_parser)]
+    replay: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct SnapshotArgs {
     /// API profile to use.
     #[clap(short, long, value_parser)]
     profile: String,
@@ -34,44 +114,176 @@ struct RunArgs {
     /// Path to the config file.
     #[clap(short, long, value_parser = get_config_file)]
     config: Option<PathBuf>,
+
+    /// Variables for the `{{ key }}` placeholders in the config file, in the form of key=value.
+    #[clap(long = "var", value_parser = parse_var, number_of_values = 1)]
+    vars: Vec<(String, String)>,
+
+    /// Environment for request1 of the profiles defined with a single `request` template.
+    #[clap(long, value_parser)]
+    left: Option<String>,
+
+    /// Directory of the snapshots, `snapshots` next to the config file by default.
+    #[clap(short, long, value_parser)]
+    dir: Option<PathBuf>,
+
+    /// Send the requests and record the responses to the cassette file.
+    #[clap(long, value_parser, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve the responses from the cassette file instead of sending the requests.
+    #[clap(long, value_parser)]
+    replay: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct CheckArgs {
+    #[clap(flatten)]
+    snapshot: SnapshotArgs,
+
+    /// Output format of the diff result.
+    #[clap(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// colorized diff for the terminal
+    Text,
+    /// machine-readable JSON report
+    Json,
+}
+
+/// Exit code of the process, so that `xdiff run` can gate deploys in scripts and CI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// the API responses are equal
+    Equal = 0,
+    /// the API responses are different
+    Different = 1,
+    /// the config file or the arguments are invalid (same as clap's usage errors)
+    ConfigError = 2,
+    /// a request failed, or any other runtime error
+    RequestError = 3,
+}
+
+/// An error together with the exit code it should lead to.
+#[derive(Debug)]
+struct Failure {
+    outcome: Outcome,
+    error: anyhow::Error,
+}
+
+impl Failure {
+    fn config(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            outcome: Outcome::ConfigError,
+            error: error.into(),
+        }
+    }
+
+    fn request(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            outcome: Outcome::RequestError,
+            error: error.into(),
+        }
+    }
+}
+
+impl From<Outcome> for ExitCode {
+    fn from(outcome: Outcome) -> Self {
+        ExitCode::from(outcome as u8)
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let args = Args::parse();
 
     let mut output: Vec<String> = Vec::new();
 
-    match args.action {
-        Action::Parse => parse(&mut output).await?,
-        Action::Run(args) => run(&mut output, args).await?,
-    }
+    let result = match args.action {
+        Action::Parse(args) => parse(&mut output, args).await,
+        Action::Run(args) => run(&mut output, *args).await,
+        Action::Snapshot(args) => snapshot(&mut output, args).await,
+        Action::Check(args) => check(&mut output, args).await,
+    };
 
     let stdout = std::io::stdout();
     let mut stdout = stdout.lock();
     for line in output {
-        write!(stdout, "{}", line)?;
+        if let Err(e) = write!(stdout, "{}", line) {
+            eprintln!("Error: {:?}", e);
+            return Outcome::RequestError.into();
+        }
     }
 
-    Ok(())
+    match result {
+        Ok(outcome) => outcome.into(),
+        Err(Failure { outcome, error }) => {
+            eprintln!("Error: {:?}", error);
+            outcome.into()
+        }
+    }
 }
 
-async fn parse(output: &mut Vec<String>) -> Result<()> {
-    let url1: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Url1")
-        .interact()?;
-    let url2: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Url2")
-        .interact()?;
+/// Build a profile from two curl commands or two URLs. An invalid one is a config error.
+async fn parse(output: &mut Vec<String>, args: ParseArgs) -> Result<Outcome, Failure> {
+    let (ctx1, ctx2) = match args.from_curl.as_slice() {
+        [cmd1, cmd2] => (
+            RequestContext::from_curl(cmd1).map_err(Failure::config)?,
+            RequestContext::from_curl(cmd2).map_err(Failure::config)?,
+        ),
+        _ => {
+            let url1: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Url1")
+                .interact()
+                .map_err(Failure::request)?;
+            let url2: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Url2")
+                .interact()
+                .map_err(Failure::request)?;
+            (
+                url1.parse().map_err(Failure::config)?,
+                url2.parse().map_err(Failure::config)?,
+            )
+        }
+    };
+    write_profile(output, ctx1, ctx2)
+        .await
+        .map_err(Failure::request)?;
+    Ok(Outcome::Equal)
+}
+
+async fn write_profile(
+    output: &mut Vec<String>,
+    ctx1: RequestContext,
+    ctx2: RequestContext,
+) -> Result<()> {
     let profile = Input::with_theme(&ColorfulTheme::default())
         .with_prompt("Give this a profile name")
         .default("default".into())
         .interact()?;
 
-    let ctx1: RequestContext = url1.parse()?;
-    let ctx2: RequestContext = url2.parse()?;
+    // the response headers to skip are picked from a sample response, but a request which may
+    // change data (e.g. an imported POST) isn't sent just for that
+    let skip_headers = match ctx1.method.is_safe() {
+        true => select_skip_headers(&ctx1).await?,
+        false => vec![],
+    };
+
+    let res = ResponseContext::new(skip_headers);
+    let config = DiffConfig::new_with_profile(profile, ctx1, ctx2, res);
+
+    let result = serde_yaml::to_string(&config)?;
 
-    let response = ctx1.send().await?;
+    output.push("---\n".to_string());
+    print_syntect(output, result, "yaml")?;
+    Ok(())
+}
+
+async fn select_skip_headers(ctx: &RequestContext) -> Result<Vec<String>> {
+    let response = ctx.send().await?;
     let headers = response
         .headers()
         .iter()
@@ -83,40 +295,272 @@ async fn parse(output: &mut Vec<String>) -> Result<()> {
         .items(&headers)
         .interact()?;
 
-    let skip_headers = chosen
-        .into_iter()
-        .map(|i| headers[i].clone())
-        .collect::<Vec<_>>();
+    Ok(chosen.into_iter().map(|i| headers[i].clone()).collect())
+}
 
-    let res = ResponseContext::new(skip_headers);
-    let config = DiffConfig::new_with_profile(profile, ctx1, ctx2, res);
+async fn run(output: &mut Vec<String>, args: RunArgs) -> Result<Outcome, Failure> {
+    let config_file = match &args.config {
+        Some(path) => path.clone(),
+        None => get_default_config("xdiff.yml").map_err(Failure::config)?,
+    };
+    let opts = LoadOptions {
+        left: args.left.clone(),
+        right: args.right.clone(),
+        ..LoadOptions::new(args.vars.iter().cloned().collect())
+    };
+    let diff_config = DiffConfig::try_load_with(&config_file, &opts)
+        .await
+        .map_err(Failure::config)?;
 
-    let result = serde_yaml::to_string(&config)?;
+    let mut profiles = select_profiles(&diff_config, &args).map_err(Failure::config)?;
+    let cassette = get_cassette(&args.record, &args.replay);
+    for ctx in profiles.iter_mut().filter_map(|(_, ctx)| ctx.as_mut().ok()) {
+        ctx.request1
+            .update(&args.extra_params)
+            .map_err(Failure::config)?;
+        ctx.request2
+            .update(&args.extra_params)
+            .map_err(Failure::config)?;
+        if let Some((file, mode)) = &cassette {
+            ctx.request1.set_cassette(file.clone(), *mode);
+            ctx.request2.set_cassette(file.clone(), *mode);
+        }
+    }
 
-    output.push("---\n".to_string());
-    print_syntect(output, result, "yaml")?;
-    Ok(())
+    if args.profile.is_some() {
+        let (_, ctx) = profiles.remove(0);
+        let ctx = ctx.map_err(Failure::config)?;
+        let result = ctx.diff().await.map_err(Failure::request)?;
+        return print_result(output, args.output, result);
+    }
+
+    let results = run_all(profiles, args.concurrency).await;
+    print_summary(output, args.output, results)
+}
+
+async fn snapshot(output: &mut Vec<String>, args: SnapshotArgs) -> Result<Outcome, Failure> {
+    let (ctx, path) = load_snapshot_profile(&args).await?;
+    let snapshot = ctx.snapshot().await.map_err(Failure::request)?;
+    snapshot.save(&path).await.map_err(Failure::request)?;
+    output.push(format!(
+        "Saved snapshot of {} to {}\n",
+        args.profile,
+        path.display()
+    ));
+    Ok(Outcome::Equal)
+}
+
+async fn check(output: &mut Vec<String>, args: CheckArgs) -> Result<Outcome, Failure> {
+    let (ctx, path) = load_snapshot_profile(&args.snapshot).await?;
+    if !path.exists() {
+        return Err(Failure::config(anyhow::anyhow!(
+            "snapshot {} not found, run `xdiff snapshot -p {}` first",
+            path.display(),
+            args.snapshot.profile
+        )));
+    }
+    let snapshot = Snapshot::load(&path).await.map_err(Failure::config)?;
+    let result = ctx.check(&snapshot).await.map_err(Failure::request)?;
+    print_result(output, args.output, result)
 }
 
-async fn run(output: &mut Vec<String>, args: RunArgs) -> Result<()> {
-    let config_file = args.config.unwrap_or(get_default_config("xdiff.yml")?);
-    let diff_config = DiffConfig::try_load(&config_file).await?;
+/// Load the profile with the extra params applied, and the path of its snapshot.
+async fn load_snapshot_profile(args: &SnapshotArgs) -> Result<(DiffContext, PathBuf), Failure> {
+    let config_file = match &args.config {
+        Some(path) => path.clone(),
+        None => get_default_config("xdiff.yml").map_err(Failure::config)?,
+    };
+    let opts = LoadOptions {
+        left: args.left.clone(),
+        left_only: true,
+        ..LoadOptions::new(args.vars.iter().cloned().collect())
+    };
+    let diff_config = DiffConfig::try_load_with(&config_file, &opts)
+        .await
+        .map_err(Failure::config)?;
+    let mut ctx = diff_config
+        .get(&args.profile)
+        .map_err(Failure::config)?
+        .clone();
+    ctx.request1
+        .update(&args.extra_params)
+        .map_err(Failure::config)?;
+    if let Some((file, mode)) = get_cassette(&args.record, &args.replay) {
+        ctx.request1.set_cassette(file, mode);
+    }
 
-    let mut config = diff_config.get(&args.profile)?.clone();
+    let dir = match &args.dir {
+        Some(dir) => dir.clone(),
+        None => config_file
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("snapshots"),
+    };
+    Ok((ctx, dir.join(format!("{}.yml", args.profile))))
+}
 
-    config.request1.update(&args.extra_params)?;
-    config.request2.update(&args.extra_params)?;
+/// Select the profiles to run by name, glob or tags, sorted by name. The profiles which couldn't
+/// be expanded are selected as well, with their error.
+fn select_profiles(
+    config: &DiffConfig,
+    args: &RunArgs,
+) -> Result<Vec<(String, Result<DiffContext>)>> {
+    if let Some(profile) = &args.profile {
+        return Ok(vec![(profile.clone(), Ok(config.get(profile)?.clone()))]);
+    }
 
-    let result = config.diff().await?;
+    let selected = |name: &str, tags: &[String]| {
+        let matched = match &args.profile_glob {
+            Some(pattern) => pattern.matches(name),
+            None => true,
+        };
+        matched && (args.tag.is_empty() || tags.iter().any(|t| args.tag.contains(t)))
+    };
+    let mut profiles: Vec<_> = config
+        .iter()
+        .filter(|(name, ctx)| selected(name, &ctx.tags))
+        .map(|(name, ctx)| (name.to_string(), Ok(ctx.clone())))
+        .chain(
+            config
+                .iter_invalid()
+                .filter(|(name, tags)| selected(name, tags))
+                .map(|(name, _)| (name.to_string(), config.get(name).cloned())),
+        )
+        .collect();
+    if profiles.is_empty() {
+        return Err(anyhow::anyhow!("no profile matches the given filters"));
+    }
+    profiles.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(profiles)
+}
 
-    match result {
-        DiffResult::Equal => {
+/// Run the profiles with at most `concurrency` of them at the same time, and return the
+/// results in the same order. A profile which couldn't be expanded fails as a config error.
+async fn run_all(
+    profiles: Vec<(String, Result<DiffContext>)>,
+    concurrency: usize,
+) -> Vec<(String, Result<DiffResult, Failure>)> {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let handles: Vec<_> = profiles
+        .into_iter()
+        .map(|(name, ctx)| {
+            let semaphore = semaphore.clone();
+            let handle = tokio::spawn(async move {
+                let ctx = ctx.map_err(Failure::config)?;
+                let _permit = semaphore.acquire().await.map_err(Failure::request)?;
+                ctx.diff().await.map_err(Failure::request)
+            });
+            (name, handle)
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for (name, handle) in handles {
+        let result = handle.await.unwrap_or_else(|e| Err(Failure::request(e)));
+        results.push((name, result));
+    }
+    results
+}
+
+fn print_result(
+    output: &mut Vec<String>,
+    format: OutputFormat,
+    result: DiffResult,
+) -> Result<Outcome, Failure> {
+    let outcome = match result {
+        DiffResult::Equal => Outcome::Equal,
+        DiffResult::Diff(_) => Outcome::Different,
+    };
+
+    match (format, result) {
+        (OutputFormat::Json, result) => {
+            let json = serde_json::to_string_pretty(&result).map_err(Failure::request)?;
+            output.push(format!("{}\n", json));
+        }
+        (OutputFormat::Text, DiffResult::Equal) => {
             output.push("API responses are equal".into());
         }
-        DiffResult::Diff(diff) => {
-            output.push(diff);
+        (OutputFormat::Text, DiffResult::Diff(report)) => {
+            output.push(report.to_string());
         }
     }
 
-    Ok(())
+    Ok(outcome)
+}
+
+/// Print the diffs and a summary of all the profiles. The outcome is the worst one among them.
+fn print_summary(
+    output: &mut Vec<String>,
+    format: OutputFormat,
+    results: Vec<(String, Result<DiffResult, Failure>)>,
+) -> Result<Outcome, Failure> {
+    let outcome = results
+        .iter()
+        .map(|(_, result)| match result {
+            Ok(DiffResult::Equal) => Outcome::Equal,
+            Ok(DiffResult::Diff(_)) => Outcome::Different,
+            Err(failure) => failure.outcome,
+        })
+        .max_by_key(|outcome| *outcome as u8)
+        .unwrap_or(Outcome::Equal);
+
+    if format == OutputFormat::Json {
+        let items = results
+            .iter()
+            .map(|(name, result)| {
+                let mut item = match result {
+                    Ok(result) => serde_json::to_value(result)?,
+                    Err(Failure { error, .. }) => {
+                        serde_json::json!({ "result": "error", "error": format!("{:#}", error) })
+                    }
+                };
+                item["profile"] = name.as_str().into();
+                Ok(item)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(Failure::request)?;
+        let json = serde_json::to_string_pretty(&items).map_err(Failure::request)?;
+        output.push(format!("{}\n", json));
+        return Ok(outcome);
+    }
+
+    for (name, result) in &results {
+        if let Ok(DiffResult::Diff(report)) = result {
+            output.push(format!("=== {} ===\n{}\n", name, report));
+        }
+    }
+
+    let width = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let (mut equal, mut different, mut errored) = (0, 0, 0);
+    output.push(format!("{:<width$}  RESULT\n", "PROFILE"));
+    for (name, result) in &results {
+        let status = match result {
+            Ok(DiffResult::Equal) => {
+                equal += 1;
+                "equal".to_string()
+            }
+            Ok(DiffResult::Diff(_)) => {
+                different += 1;
+                "different".to_string()
+            }
+            Err(Failure { error, .. }) => {
+                errored += 1;
+                format!("error: {:#}", error)
+            }
+        };
+        output.push(format!("{:<width$}  {}\n", name, status));
+    }
+    output.push(format!(
+        "\n{} profiles: {} equal, {} different, {} errored\n",
+        results.len(),
+        equal,
+        different,
+        errored
+    ));
+
+    Ok(outcome)
 }
@@ -4,10 +4,15 @@ use colored::Colorize;
 use dialoguer::{theme::ColorfulTheme, Input};
 use mime::Mime;
 use serde_json::Value;
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
 
-use xreq_cli_utils::{get_config_file, get_default_config, parse_key_val, print_syntect};
-use xreq_lib::{KeyVal, RequestConfig, RequestContext, Response};
+use xreq_cli_utils::{
+    get_cassette, get_config_file, get_default_config, parse_key_val, parse_var, print_syntect,
+};
+use xreq_lib::{ExportFormat, KeyVal, LoadOptions, RequestConfig, RequestContext, Response};
 
 /// HTTP request tool just as curl/httpie, but easier to use.
 #[derive(Parser, Debug)]
@@ -23,6 +28,8 @@ enum Action {
     Parse(ParseArgs),
     /// Send API request based on a given profile.
     Run(RunArgs),
+    /// Print a profile as a curl, HTTPie or wget command.
+    Export(ExportArgs),
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -31,8 +38,11 @@ struct ParseArgs {
     #[clap(short, long, value_parser, default_value = "default")]
     profile: String,
     /// URL to parse.
-    #[clap(value_parser)]
+    #[clap(value_parser, conflicts_with = "from_curl")]
     url: Option<String>,
+    /// curl command to parse instead of a URL, e.g. one copied from the browser's devtools.
+    #[clap(long, value_parser)]
+    from_curl: Option<String>,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -51,6 +61,64 @@ struct RunArgs {
     /// Path to the config file.
     #[clap(short, long, value_parser = get_config_file)]
     config: Option<PathBuf>,
+
+    /// Variables for the `{{ key }}` placeholders in the config file, in the form of key=value.
+    /// They take precedence over the `vars` in the config file and the environment variables.
+    #[clap(long = "var", value_parser = parse_var, number_of_values = 1)]
+    vars: Vec<(String, String)>,
+
+    /// Read the request body from the file, or from stdin if it's '-'. The content type is
+    /// inferred from the file extension if the profile doesn't set it.
+    #[clap(long, value_parser)]
+    body: Option<PathBuf>,
+
+    /// Send the requests and record the responses to the cassette file.
+    #[clap(long, value_parser, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve the responses from the cassette file instead of sending the requests.
+    #[clap(long, value_parser)]
+    replay: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct ExportArgs {
+    /// API profile to use.
+    #[clap(short, long, value_parser)]
+    profile: String,
+
+    /// Command line tool to export to.
+    #[clap(short, long, value_enum, default_value_t = Format::Curl)]
+    format: Format,
+
+    /// Extra parameters to pass to the API, as for `run`.
+    #[clap(short, value_parser = parse_key_val, number_of_values = 1)]
+    extra_params: Vec<KeyVal>,
+
+    /// Path to the config file.
+    #[clap(short, long, value_parser = get_config_file)]
+    config: Option<PathBuf>,
+
+    /// Variables for the `{{ key }}` placeholders in the config file, in the form of key=value.
+    #[clap(long = "var", value_parser = parse_var, number_of_values = 1)]
+    vars: Vec<(String, String)>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Curl,
+    Httpie,
+    Wget,
+}
+
+impl From<Format> for ExportFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Curl => ExportFormat::Curl,
+            Format::Httpie => ExportFormat::Httpie,
+            Format::Wget => ExportFormat::Wget,
+        }
+    }
 }
 
 #[tokio::main]
@@ -62,6 +130,7 @@ async fn main() -> Result<()> {
     match args.action {
         Action::Parse(args) => parse(&mut output, args)?,
         Action::Run(args) => run(&mut output, args).await?,
+        Action::Export(args) => export(&mut output, args).await?,
     }
 
     let stdout = std::io::stdout();
@@ -73,10 +142,21 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-fn parse(output: &mut Vec<String>, ParseArgs { profile, url }: ParseArgs) -> Result<()> {
-    let (profile, url) = match url {
-        Some(url) => (profile, url),
-        None => {
+fn parse(
+    output: &mut Vec<String>,
+    ParseArgs {
+        profile,
+        url,
+        from_curl,
+    }: ParseArgs,
+) -> Result<()> {
+    let (profile, url) = match (url, from_curl) {
+        (_, Some(cmd)) => {
+            let ctx = RequestContext::from_curl(&cmd)?;
+            return print_config(output, RequestConfig::new_with_profile(profile, ctx));
+        }
+        (Some(url), None) => (profile, url),
+        (None, None) => {
             let url = Input::with_theme(&ColorfulTheme::default())
                 .with_prompt("Url to parse")
                 .interact()?;
@@ -89,8 +169,10 @@ fn parse(output: &mut Vec<String>, ParseArgs { profile, url }: ParseArgs) -> Res
     };
 
     let ctx: RequestContext = url.parse()?;
-    let config = RequestConfig::new_with_profile(profile, ctx);
+    print_config(output, RequestConfig::new_with_profile(profile, ctx))
+}
 
+fn print_config(output: &mut Vec<String>, config: RequestConfig) -> Result<()> {
     let result = serde_yaml::to_string(&config)?;
 
     output.push("---\n".to_string());
@@ -99,13 +181,33 @@ fn parse(output: &mut Vec<String>, ParseArgs { profile, url }: ParseArgs) -> Res
 }
 
 async fn run(output: &mut Vec<String>, args: RunArgs) -> Result<()> {
-    let config_file = args.config.unwrap_or(get_default_config("xreq.yml")?);
+    let config_file = match args.config {
+        Some(path) => path,
+        None => get_default_config("xreq.yml")?,
+    };
 
-    let request_config = RequestConfig::try_load(&config_file).await?;
+    let opts = LoadOptions::new(args.vars.into_iter().collect());
+    let request_config = RequestConfig::try_load_with(&config_file, &opts).await?;
 
     let mut config = request_config.get(&args.profile)?.clone();
 
     config.update(&args.extra_params)?;
+    if let Some((file, mode)) = get_cassette(&args.record, &args.replay) {
+        config.set_cassette(file, mode);
+    }
+
+    match args.body {
+        Some(path) if path == Path::new("-") => {
+            let mut body = String::new();
+            std::io::stdin().read_to_string(&mut body)?;
+            config.set_body(&body)?;
+        }
+        Some(path) => {
+            config.body = None;
+            config.body_file = Some(path);
+        }
+        None => {}
+    }
 
     let resp = config.send().await?;
 
@@ -123,6 +225,19 @@ async fn run(output: &mut Vec<String>, args: RunArgs) -> Result<()> {
     Ok(())
 }
 
+async fn export(output: &mut Vec<String>, args: ExportArgs) -> Result<()> {
+    let config_file = match args.config {
+        Some(path) => path,
+        None => get_default_config("xreq.yml")?,
+    };
+    let opts = LoadOptions::new(args.vars.into_iter().collect());
+    let request_config = RequestConfig::try_load_with(&config_file, &opts).await?;
+    let mut ctx = request_config.get(&args.profile)?.clone();
+    ctx.update(&args.extra_params)?;
+
+    print_syntect(output, ctx.export(args.format.into())?, "sh")
+}
+
 fn print_status(output: &mut Vec<String>, resp: &Response) {
     let status = format!("{:?} {}", resp.version(), resp.status()).blue();
     output.push(format!("{}\n", status));